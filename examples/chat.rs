@@ -4,44 +4,56 @@ use bevy::app::ScheduleRunnerPlugin;
 use bevy::log::LogPlugin;
 use bevy::prelude::*;
 use bevy::time::TimePlugin;
-use bevy_nest::prelude::*;
-
-#[derive(Component)]
-struct Player(ClientId);
+use bevy_nest::{connection::*, prelude::*};
 
 #[derive(Resource)]
 struct WhoTimer(Timer);
 
 fn setup_network(server: Res<Server>) {
-    server.listen("127.0.0.1:4000");
+    server.listen_with(
+        "127.0.0.1:4000",
+        ListenerConfig {
+            greeting: Some("Welcome to the chat!".into()),
+            negotiate: vec![(WILL, GMCP)],
+            ..default()
+        },
+    );
 }
 
 fn handle_events(
-    mut commands: Commands,
     mut events: EventReader<NetworkEvent>,
     mut outbox: EventWriter<Outbox>,
-    players: Query<(Entity, &Player)>,
+    players: Query<&Connection>,
 ) {
     for event in events.read() {
         match event {
-            NetworkEvent::Connected(id) => {
-                commands.spawn(Player(*id));
-
-                outbox.send_command(*id, vec![IAC, WILL, GMCP]);
+            NetworkEvent::Listening { local_addr, .. } => {
+                info!("Chat server listening on {local_addr}");
+            }
+            NetworkEvent::Connected(id, info) => {
+                info!("{id:?} connected from {}", info.peer_addr);
 
-                for (_, player) in players.iter() {
-                    outbox.send_text(player.0, format!("{id:?} connected"));
+                for player in players.iter().filter(|player| player.id != *id) {
+                    outbox.send_text(player.id, format!("{id:?} connected"));
                 }
             }
-            NetworkEvent::Disconnected(id) => {
-                if let Some((entity, _)) = players.iter().find(|(_, c)| c.0 == *id) {
-                    commands.entity(entity).despawn();
-
-                    for (_, player) in players.iter() {
-                        outbox.send_text(player.0, format!("{id:?} disconnected"));
-                    }
+            NetworkEvent::Reconnected(id, _) => {
+                info!("{id:?} is back after a copyover");
+            }
+            NetworkEvent::Disconnected(id, _) => {
+                for player in players.iter() {
+                    outbox.send_text(player.id, format!("{id:?} disconnected"));
                 }
             }
+            NetworkEvent::InputFlood(id) => {
+                warn!("{id:?} is flooding");
+            }
+            NetworkEvent::OutboxOverflow(id) => {
+                warn!("{id:?} isn't keeping up with its output");
+            }
+            NetworkEvent::ConnectionRejected { addr, reason } => {
+                info!("Rejected connection from {addr}: {reason:?}");
+            }
             NetworkEvent::Error(error) => {
                 error!("Network Error: {error:?}");
             }
@@ -49,16 +61,10 @@ fn handle_events(
     }
 }
 
-fn handle_messages(
-    mut inbox: EventReader<Inbox>,
-    mut outbox: EventWriter<Outbox>,
-    players: Query<(Entity, &Player)>,
-) {
+fn handle_messages(mut inbox: EventReader<Inbox>, mut outbox: EventWriter<Outbox>) {
     for message in inbox.read() {
         if let Message::Text(text) = &message.content {
-            for (_, player) in players.iter() {
-                outbox.send_text(player.0, format!("{:?}: {text}", message.from));
-            }
+            outbox.send_to_all(format!("{:?}: {text}", message.from));
         }
     }
 }
@@ -79,11 +85,11 @@ fn who_online(
     time: Res<Time>,
     mut who_timer: ResMut<WhoTimer>,
     mut outbox: EventWriter<Outbox>,
-    players: Query<&Player>,
+    players: Query<&Connection>,
 ) {
     if who_timer.0.tick(time.delta()).just_finished() {
         for player in players.iter() {
-            outbox.send_gmcp(player.0, OnlineCount(players.iter().len()).into());
+            outbox.send_gmcp(player.id, OnlineCount(players.iter().len()).into());
         }
     }
 }
@@ -100,7 +106,8 @@ fn main() {
             TypeRegistrationPlugin,
             TimePlugin,
             ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(1.0 / 60.0)),
-            NestPlugin,
+            NestPlugin::default(),
+            ConnectionPlugin::default(),
         ))
         .add_systems(Startup, setup_network)
         .add_systems(Update, (handle_events, handle_messages, who_online))
@@ -1,4 +1,5 @@
 use crossbeam_channel::{unbounded, Receiver, Sender};
+use tokio::sync::Semaphore;
 
 pub(crate) struct Channel<T> {
     pub(crate) sender: Sender<T>,
@@ -12,3 +13,29 @@ impl<T> Channel<T> {
         Self { sender, receiver }
     }
 }
+
+// Room for what's waiting between the network and the next update. When it runs out,
+// the network waits for an update to make room instead of blocking a thread.
+pub(crate) struct Capacity(Option<Semaphore>);
+
+impl Capacity {
+    pub(crate) fn new(capacity: Option<usize>) -> Self {
+        Self(capacity.map(Semaphore::new))
+    }
+
+    // Wait for room for one more.
+    pub(crate) async fn reserve(&self) {
+        if let Some(semaphore) = &self.0 {
+            if let Ok(permit) = semaphore.acquire().await {
+                permit.forget();
+            }
+        }
+    }
+
+    // Make room again once some have been taken out.
+    pub(crate) fn release(&self, count: usize) {
+        if let Some(semaphore) = &self.0 {
+            semaphore.add_permits(count);
+        }
+    }
+}
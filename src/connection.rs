@@ -0,0 +1,156 @@
+//! An optional entity for every connection, for games that would rather think in
+//! entities than [`ClientId`]s.
+//!
+//! With the [`ConnectionPlugin`] added, an entity with a [`Connection`] component is
+//! spawned for every client when it connects, and despawned when it disconnects.
+//! The [`Connections`] resource finds a client's entity without searching for it.
+//!
+//! ```rust
+//! use bevy::prelude::*;
+//! use bevy_nest::{connection::*, prelude::*, testing::MockClient};
+//!
+//! #[derive(Component)]
+//! struct Health(u32);
+//!
+//! fn spawn_player(mut commands: Commands, players: Query<Entity, Added<Connection>>) {
+//!     for entity in players.iter() {
+//!         commands.entity(entity).insert(Health(100));
+//!     }
+//! }
+//!
+//! let mut app = App::new();
+//!
+//! app.add_plugins((NestPlugin::default(), ConnectionPlugin::default()))
+//!     .add_systems(Update, spawn_player);
+//!
+//! let client = MockClient::connect(&mut app);
+//! app.update();
+//!
+//! let entity = app.world().resource::<Connections>().entity(&client.id()).unwrap();
+//!
+//! assert_eq!(app.world().get::<Connection>(entity).unwrap().id, client.id());
+//! assert_eq!(app.world().get::<Health>(entity).unwrap().0, 100);
+//! ```
+
+use std::{collections::HashMap, net::SocketAddr};
+
+use bevy::prelude::*;
+
+use crate::{
+    events::NetworkEvent,
+    listener::ListenerId,
+    plugin::{NestSchedules, NestSet},
+    server::ClientId,
+};
+
+/// A connected client, on the entity the [`ConnectionPlugin`] spawned for it.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Connection {
+    pub id: ClientId,
+    /// The address the client connected from.
+    pub addr: SocketAddr,
+    /// The listener the client connected through.
+    pub listener: ListenerId,
+}
+
+/// Adds the [`Connections`] resource and spawns an entity for every connection.
+/// Requires the [`NestPlugin`](crate::plugin::NestPlugin), which has to be added
+/// first.
+pub struct ConnectionPlugin {
+    /// Whether a client's entity is despawned when it disconnects. Otherwise only the
+    /// [`Connection`] component is removed, so the entity can live on, e.g. as a
+    /// character that stays in the world.
+    pub despawn_on_disconnect: bool,
+}
+
+impl Default for ConnectionPlugin {
+    fn default() -> Self {
+        Self {
+            despawn_on_disconnect: true,
+        }
+    }
+}
+
+impl Plugin for ConnectionPlugin {
+    fn build(&self, app: &mut App) {
+        let schedule = app
+            .world()
+            .get_resource::<NestSchedules>()
+            .expect("The NestPlugin has to be added before the ConnectionPlugin")
+            .receive;
+
+        app.insert_resource(Connections {
+            despawn_on_disconnect: self.despawn_on_disconnect,
+            entities: HashMap::new(),
+        });
+
+        app.add_systems(schedule, update_connections.after(NestSet::Receive));
+    }
+}
+
+/// The entity of every connected client.
+#[derive(Resource)]
+pub struct Connections {
+    despawn_on_disconnect: bool,
+    entities: HashMap<ClientId, Entity>,
+}
+
+impl Connections {
+    /// The entity spawned for a client, if it's connected.
+    pub fn entity(&self, client_id: &ClientId) -> Option<Entity> {
+        self.entities.get(client_id).copied()
+    }
+
+    /// Every connected client and its entity.
+    pub fn iter(&self) -> impl Iterator<Item = (ClientId, Entity)> + '_ {
+        self.entities.iter().map(|(id, entity)| (*id, *entity))
+    }
+
+    /// How many clients are connected.
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+}
+
+// Spawn an entity for every client that connects, and clean up after the ones that
+// disconnect.
+fn update_connections(
+    mut commands: Commands,
+    mut connections: ResMut<Connections>,
+    mut events: EventReader<NetworkEvent>,
+) {
+    for event in events.read() {
+        match event {
+            NetworkEvent::Connected(id, info) | NetworkEvent::Reconnected(id, info) => {
+                let entity = commands
+                    .spawn(Connection {
+                        id: *id,
+                        addr: info.peer_addr,
+                        listener: info.listener,
+                    })
+                    .id();
+
+                connections.entities.insert(*id, entity);
+            }
+            NetworkEvent::Disconnected(id, _) => {
+                let Some(entity) = connections.entities.remove(id) else {
+                    continue;
+                };
+
+                // The game may have despawned it already.
+                if let Some(mut entity) = commands.get_entity(entity) {
+                    if connections.despawn_on_disconnect {
+                        entity.despawn();
+                    } else {
+                        entity.remove::<Connection>();
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
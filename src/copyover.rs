@@ -0,0 +1,211 @@
+// The state handed from one process to the next in a copyover.
+//
+// It's a text file with the pid of the process, which stays the same across `exec`,
+// followed by a line per client:
+//
+//     pid 1234
+//     client <client id> <fd> <peer addr> <local addr> <listener id> <option:verb,...>
+
+use std::{
+    fs, io,
+    net::SocketAddr,
+    os::fd::{FromRawFd, OwnedFd, RawFd},
+    path::Path,
+};
+
+use bevy::prelude::*;
+use tokio::net::{TcpStream, UnixStream};
+use uuid::Uuid;
+
+use crate::{
+    listener::ListenerId,
+    server::{ClientId, ClientInfo},
+    telnet::TelnetState,
+    transport::Transport,
+};
+
+// A client as it's written to the state file.
+pub(crate) struct SavedClient {
+    pub(crate) id: ClientId,
+    pub(crate) fd: RawFd,
+    pub(crate) info: ClientInfo,
+    pub(crate) telnet: TelnetState,
+}
+
+// Write the state file for the clients being handed over.
+pub(crate) fn save(path: &Path, clients: &[SavedClient]) -> io::Result<()> {
+    let mut state = format!("pid {}\n", std::process::id());
+
+    for client in clients {
+        let replies = client
+            .telnet
+            .replies
+            .iter()
+            .map(|(option, verb)| format!("{option}:{verb}"))
+            .collect::<Vec<_>>();
+
+        state.push_str(&format!(
+            "client {} {} {} {} {} {}\n",
+            client.id.0,
+            client.fd,
+            client.info.peer_addr,
+            client.info.local_addr,
+            client.info.listener.0,
+            if replies.is_empty() {
+                "-".into()
+            } else {
+                replies.join(",")
+            },
+        ));
+    }
+
+    fs::write(path, state)
+}
+
+// Read and remove the state file. There's nothing to restore if it doesn't exist or
+// it was written by another process, e.g. one that crashed mid-copyover.
+pub(crate) fn load(path: &Path) -> io::Result<Vec<SavedClient>> {
+    let state = match fs::read_to_string(path) {
+        Ok(state) => state,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+
+    fs::remove_file(path)?;
+
+    let mut lines = state.lines();
+
+    let pid = lines
+        .next()
+        .and_then(|line| line.strip_prefix("pid "))
+        .and_then(|pid| pid.parse::<u32>().ok());
+
+    if pid != Some(std::process::id()) {
+        warn!(
+            "Ignoring copyover state from another process: {}",
+            path.display()
+        );
+
+        return Ok(Vec::new());
+    }
+
+    // A bad line only costs that one client.
+    Ok(lines
+        .filter_map(|line| {
+            let client = parse_client(line).filter(|client| is_socket(client.fd));
+
+            if client.is_none() {
+                warn!("Skipping invalid copyover state: {line}");
+
+                // Close the client's socket, if the line says which it is.
+                if let Some(fd) = line.split(' ').nth(2).and_then(parse_fd) {
+                    if is_socket(fd) {
+                        drop(take_fd(fd));
+                    }
+                }
+            }
+
+            client
+        })
+        .collect())
+}
+
+fn parse_client(line: &str) -> Option<SavedClient> {
+    let mut fields = line.strip_prefix("client ")?.split(' ');
+
+    let id = ClientId(Uuid::parse_str(fields.next()?).ok()?);
+    let fd = parse_fd(fields.next()?)?;
+    let peer_addr = fields.next()?.parse::<SocketAddr>().ok()?;
+    let local_addr = fields.next()?.parse::<SocketAddr>().ok()?;
+    let listener = ListenerId(Uuid::parse_str(fields.next()?).ok()?);
+    let mut telnet = TelnetState::default();
+
+    match fields.next()? {
+        "-" => {}
+        replies => {
+            for reply in replies.split(',') {
+                let (option, verb) = reply.split_once(':')?;
+
+                telnet
+                    .replies
+                    .insert(option.parse().ok()?, verb.parse().ok()?);
+            }
+        }
+    }
+
+    Some(SavedClient {
+        id,
+        fd,
+        info: ClientInfo {
+            peer_addr,
+            local_addr,
+            listener,
+        },
+        telnet,
+    })
+}
+
+// A client's fd, which can't be one of the standard streams.
+fn parse_fd(fd: &str) -> Option<RawFd> {
+    fd.parse::<RawFd>().ok().filter(|fd| *fd > 2)
+}
+
+// Let a socket be inherited across `exec`, or stop it from being.
+pub(crate) fn set_inheritable(fd: RawFd, inheritable: bool) -> io::Result<()> {
+    // SAFETY: fcntl only reads and sets the descriptor flags of the given fd.
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFD);
+
+        if flags == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let flags = if inheritable {
+            flags & !libc::FD_CLOEXEC
+        } else {
+            flags | libc::FD_CLOEXEC
+        };
+
+        if libc::fcntl(fd, libc::F_SETFD, flags) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+// Whether an fd is an open socket, so it's safe to take.
+fn is_socket(fd: RawFd) -> bool {
+    // SAFETY: fstat only writes to the zeroed stat buffer it's given.
+    unsafe {
+        let mut stat = std::mem::zeroed::<libc::stat>();
+
+        libc::fstat(fd, &mut stat) == 0 && stat.st_mode & libc::S_IFMT == libc::S_IFSOCK
+    }
+}
+
+// Wrap an inherited TCP or Unix domain socket connection. This must be called from
+// within the server's runtime.
+pub(crate) fn stream(fd: OwnedFd) -> io::Result<Box<dyn Transport>> {
+    let stream = std::net::TcpStream::from(fd);
+
+    // Only sockets with an IP address have a TCP local address.
+    if stream.local_addr().is_ok() {
+        stream.set_nonblocking(true)?;
+
+        return Ok(Box::new(TcpStream::from_std(stream)?));
+    }
+
+    let stream = std::os::unix::net::UnixStream::from(OwnedFd::from(stream));
+
+    stream.set_nonblocking(true)?;
+
+    Ok(Box::new(UnixStream::from_std(stream)?))
+}
+
+// Take ownership of an inherited socket.
+pub(crate) fn take_fd(fd: RawFd) -> OwnedFd {
+    // SAFETY: the state file was written by this process before it exec'd, and each
+    // fd in it is only taken once since the file is removed when it's read.
+    unsafe { OwnedFd::from_raw_fd(fd) }
+}
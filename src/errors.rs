@@ -1,15 +1,24 @@
+use std::net::SocketAddr;
+
 use thiserror::Error;
 
-use crate::server::ClientId;
+use crate::{listener::ListenerId, server::ClientId};
 
 #[derive(Error, Debug)]
 pub enum NetworkError {
     #[error("An error occured when accepting a new connnection: {0}")]
     Accept(std::io::Error),
-    #[error("An error occured when trying to start listening for new connections: {0}")]
-    Listen(std::io::Error),
+    #[error("An error occured during the handshake with a new connection: {0} {1}")]
+    Handshake(std::io::Error, SocketAddr),
+    #[error("An error occured when trying to start listening for new connections: {0} {1:?}")]
+    Listen(std::io::Error, ListenerId),
     #[error("An error occured when reading from socket: {0} {1:?}")]
     SocketRead(std::io::Error, ClientId),
     #[error("An error occured when writing to socket: {0} {1:?}")]
     SocketWrite(std::io::Error, ClientId),
 }
+
+/// An IP range that couldn't be parsed as a [`Cidr`](crate::cidr::Cidr).
+#[derive(Error, Debug)]
+#[error("Invalid CIDR range: {0}")]
+pub struct InvalidCidr(pub String);
@@ -1,23 +1,117 @@
+use std::{net::SocketAddr, sync::Arc};
+
+#[cfg(unix)]
+use std::os::fd::RawFd;
+
 use crate::errors::NetworkError;
-use crate::server::ClientId;
+use crate::limits::RejectReason;
+use crate::listener::{ListenerConfig, ListenerId};
+use crate::server::{ClientId, ClientInfo};
+use crate::telnet::TelnetState;
+use crate::transport::Transport;
 
 use bevy::prelude::*;
-use tokio::net::TcpStream;
 
-#[derive(Debug)]
 pub(crate) struct IncomingConnection {
-    pub(crate) socket: TcpStream,
+    pub(crate) id: ClientId,
+    pub(crate) stream: Box<dyn Transport>,
+    pub(crate) info: ClientInfo,
+    pub(crate) config: Arc<ListenerConfig>,
+    // The raw socket, for connections that can be handed over in a copyover.
+    #[cfg(unix)]
+    pub(crate) fd: Option<RawFd>,
+    // The telnet state of a client restored from a copyover.
+    pub(crate) restored: Option<TelnetState>,
+}
+
+impl std::fmt::Debug for IncomingConnection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IncomingConnection")
+            .field("id", &self.id)
+            .field("info", &self.info)
+            .field("config", &self.config)
+            .field("restored", &self.restored)
+            .finish_non_exhaustive()
+    }
+}
+
+// Something received from the network. Events and messages share one channel, so
+// each client's are seen in the order they happened.
+#[derive(Debug)]
+pub(crate) enum Received {
+    Event(NetworkEvent),
+    Inbox(Inbox),
+}
+
+impl From<NetworkEvent> for Received {
+    fn from(event: NetworkEvent) -> Self {
+        Received::Event(event)
+    }
+}
+
+impl From<Inbox> for Received {
+    fn from(message: Inbox) -> Self {
+        Received::Inbox(message)
+    }
 }
 
 #[derive(Debug, Event)]
 pub enum NetworkEvent {
-    Connected(ClientId),
-    Disconnected(ClientId),
+    /// A listener is bound and accepting connections. `local_addr` is the address it
+    /// actually bound to, so listening on port `0` reports the port the OS picked.
+    Listening {
+        listener: ListenerId,
+        local_addr: SocketAddr,
+    },
+    /// A client connected. The [`ClientInfo`] is also available through
+    /// [`Server::client_info`](crate::server::Server::client_info) until the client disconnects.
+    Connected(ClientId, ClientInfo),
+    /// A client was handed over from the previous process by a copyover. It keeps
+    /// its [`ClientId`] and isn't sent the greeting or negotiation again.
+    ///
+    /// See: [`Server::copyover`](crate::server::Server::copyover)
+    Reconnected(ClientId, ClientInfo),
+    Disconnected(ClientId, DisconnectReason),
+    /// A client sent more input than its [`InputLimit`](crate::limits::InputLimit)
+    /// allows. This is sent once each time the client starts flooding, whatever the
+    /// [`FloodPolicy`](crate::limits::FloodPolicy) does about it.
+    InputFlood(ClientId),
+    /// A message didn't fit in a client's outbox because of its
+    /// [`OutboxLimit`](crate::limits::OutboxLimit). This is sent once each time the
+    /// outbox fills up, whatever the [`OverflowPolicy`](crate::limits::OverflowPolicy)
+    /// does about it.
+    OutboxOverflow(ClientId),
+    /// A connection was closed before it became a client because it broke one of
+    /// the [`ConnectionLimits`](crate::limits::ConnectionLimits).
+    ConnectionRejected {
+        addr: SocketAddr,
+        reason: RejectReason,
+    },
     Error(NetworkError),
 }
 
+/// Why a client was disconnected, sent with [`NetworkEvent::Disconnected`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// The client closed the connection.
+    Closed,
+    /// Reading from or writing to the connection failed.
+    Error,
+    /// The game disconnected the client with [`Server::disconnect`](crate::server::Server::disconnect).
+    Kicked,
+    /// The client sent more input than its [`InputLimit`](crate::limits::InputLimit) allows.
+    InputFlood,
+    /// The client fell too far behind reading its output for its [`OutboxLimit`](crate::limits::OutboxLimit).
+    SlowConsumer,
+    /// The client didn't send a line for longer than its [`IdleTimeout`](crate::listener::IdleTimeout).
+    IdleTimeout,
+    /// The client didn't finish telnet negotiation within its listener's
+    /// [`negotiation_timeout`](crate::listener::ListenerConfig::negotiation_timeout).
+    NegotiationTimeout,
+}
+
 /// Data to be sent to a client over the GMCP protocol.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Payload {
     pub package: String,
     pub subpackage: Option<String>,
@@ -25,7 +119,7 @@ pub struct Payload {
 }
 
 /// A message sent from the server to a client or vice versa.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Message {
     /// Just your regular text message. This is appended with a newline when sent
     /// to the client.
@@ -102,10 +196,10 @@ pub struct Inbox {
 ///             if content == "ping" {
 ///                 // There are a few ways to send messages to the outbox:
 ///                 // 1. Build the message and send it to the outbox.
-///                 outbox.send(Outbox { to: message.from, content: Message::Text("pong!".into()) });
+///                 outbox.send(Outbox { to: message.from.into(), content: Message::Text("pong!".into()) });
 ///                 // 2. Use the From trait, which is implemented for &str, String, Vec<u8>, and Payload
 ///                 // for creating text, commands, and GMCP messages respectively.
-///                 outbox.send(Outbox { to: message.from, content: "pong!".into() });
+///                 outbox.send(Outbox { to: message.from.into(), content: "pong!".into() });
 ///                 // 3. Use the extension trait OutboxWriterExt, which provides convenience methods.
 ///                 outbox.send_text(message.from, "pong!");
 ///             }
@@ -115,22 +209,58 @@ pub struct Inbox {
 /// ```
 #[derive(Debug, Event)]
 pub struct Outbox {
-    pub to: ClientId,
+    pub to: Recipient,
     pub content: Message,
 }
 
+/// Who an [`Outbox`] message is for. Messages for more than one client are only
+/// encoded once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Recipient {
+    Client(ClientId),
+    /// The client on an entity with a [`Connection`](crate::connection::Connection)
+    /// component, like the ones the
+    /// [`ConnectionPlugin`](crate::connection::ConnectionPlugin) spawns. It's looked up
+    /// when the outbox is sent, and the message is dropped if the entity has no
+    /// connection by then.
+    Entity(Entity),
+    Many(Vec<ClientId>),
+    /// Everyone in a group, see [`Server::join_group`](crate::server::Server::join_group).
+    Group(String),
+    /// Every connected client.
+    All,
+}
+
+impl From<ClientId> for Recipient {
+    fn from(id: ClientId) -> Self {
+        Recipient::Client(id)
+    }
+}
+
+impl From<Entity> for Recipient {
+    fn from(entity: Entity) -> Self {
+        Recipient::Entity(entity)
+    }
+}
+
 /// Extension trait for [`EventWriter<Outbox>`] to make sending messages easier.
 pub trait OutboxWriterExt {
     fn send_text(&mut self, to: ClientId, text: impl Into<String>);
     fn send_command(&mut self, to: ClientId, command: impl Into<Vec<u8>>);
     fn send_gmcp(&mut self, to: ClientId, payload: Payload);
+    fn send_text_to_entity(&mut self, to: Entity, text: impl Into<String>);
+    fn send_command_to_entity(&mut self, to: Entity, command: impl Into<Vec<u8>>);
+    fn send_gmcp_to_entity(&mut self, to: Entity, payload: Payload);
+    fn send_to_all(&mut self, message: impl Into<Message>);
+    fn send_to_many(&mut self, to: &[ClientId], message: impl Into<Message>);
+    fn send_to_group(&mut self, group: impl Into<String>, message: impl Into<Message>);
 }
 
 impl OutboxWriterExt for EventWriter<'_, Outbox> {
     /// Sends a [`Message::Text`] to a client.
     fn send_text(&mut self, to: ClientId, text: impl Into<String>) {
         self.send(Outbox {
-            to,
+            to: to.into(),
             content: Message::Text(text.into()),
         });
     }
@@ -138,7 +268,7 @@ impl OutboxWriterExt for EventWriter<'_, Outbox> {
     /// Sends a [`Message::Command`] to a client.
     fn send_command(&mut self, to: ClientId, command: impl Into<Vec<u8>>) {
         self.send(Outbox {
-            to,
+            to: to.into(),
             content: Message::Command(command.into()),
         });
     }
@@ -146,8 +276,56 @@ impl OutboxWriterExt for EventWriter<'_, Outbox> {
     /// Sends a [`Message::GMCP`] to a client.
     fn send_gmcp(&mut self, to: ClientId, payload: Payload) {
         self.send(Outbox {
-            to,
+            to: to.into(),
             content: Message::GMCP(payload),
         });
     }
+
+    /// Sends a [`Message::Text`] to the client on an entity.
+    fn send_text_to_entity(&mut self, to: Entity, text: impl Into<String>) {
+        self.send(Outbox {
+            to: to.into(),
+            content: Message::Text(text.into()),
+        });
+    }
+
+    /// Sends a [`Message::Command`] to the client on an entity.
+    fn send_command_to_entity(&mut self, to: Entity, command: impl Into<Vec<u8>>) {
+        self.send(Outbox {
+            to: to.into(),
+            content: Message::Command(command.into()),
+        });
+    }
+
+    /// Sends a [`Message::GMCP`] to the client on an entity.
+    fn send_gmcp_to_entity(&mut self, to: Entity, payload: Payload) {
+        self.send(Outbox {
+            to: to.into(),
+            content: Message::GMCP(payload),
+        });
+    }
+
+    /// Sends a message to every connected client.
+    fn send_to_all(&mut self, message: impl Into<Message>) {
+        self.send(Outbox {
+            to: Recipient::All,
+            content: message.into(),
+        });
+    }
+
+    /// Sends a message to each of the given clients.
+    fn send_to_many(&mut self, to: &[ClientId], message: impl Into<Message>) {
+        self.send(Outbox {
+            to: Recipient::Many(to.to_vec()),
+            content: message.into(),
+        });
+    }
+
+    /// Sends a message to everyone in a group.
+    fn send_to_group(&mut self, group: impl Into<String>, message: impl Into<Message>) {
+        self.send(Outbox {
+            to: Recipient::Group(group.into()),
+            content: message.into(),
+        });
+    }
 }
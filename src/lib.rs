@@ -1,10 +1,41 @@
 //! A telnet plugin for getting MUDdy in Bevy.
 
+// Log at a level picked at runtime, for the routine activity that
+// `NestPlugin::log_level` applies to.
+macro_rules! log_at {
+    ($level:expr, $($arg:tt)+) => {
+        match $level {
+            bevy::log::Level::ERROR => bevy::log::error!($($arg)+),
+            bevy::log::Level::WARN => bevy::log::warn!($($arg)+),
+            bevy::log::Level::INFO => bevy::log::info!($($arg)+),
+            bevy::log::Level::DEBUG => bevy::log::debug!($($arg)+),
+            bevy::log::Level::TRACE => bevy::log::trace!($($arg)+),
+        }
+    };
+}
+
 mod channel;
+pub mod cidr;
+pub mod connection;
+#[cfg(unix)]
+mod copyover;
 pub mod errors;
 pub mod events;
+pub mod limits;
+pub mod listener;
+pub mod metrics;
 pub mod plugin;
 pub mod prelude;
+mod proxy;
+mod queue;
 pub mod server;
+pub mod session;
 mod systems;
 pub mod telnet;
+pub mod testing;
+mod timers;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod transport;
+#[cfg(feature = "websocket")]
+pub mod websocket;
@@ -0,0 +1,565 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::*;
+
+use crate::{cidr::Cidr, listener::ListenerId, server::ClientInfo};
+
+/// Limits on who can connect and how many connections they can have open at once.
+/// This is a resource, so the limits can be changed at any time and apply from the
+/// next connection on.
+///
+/// Unix socket clients have no address of their own, so only
+/// [`max_total`](Self::max_total) applies to them unless the listener reads the real
+/// addresses from a [PROXY header](crate::listener::ProxyProtocol).
+///
+/// ```rust
+/// use bevy::prelude::*;
+/// use bevy_nest::prelude::*;
+///
+/// fn ban(mut limits: ResMut<ConnectionLimits>) {
+///     limits.deny.push("203.0.113.0/24".parse().unwrap());
+/// }
+///
+/// let mut app = App::new();
+///
+/// app.add_plugins(NestPlugin::default())
+///     .insert_resource(ConnectionLimits {
+///         max_per_ip: Some(5),
+///         max_total: Some(500),
+///         message: Some("Too many connections, try again later.".into()),
+///         full_message: Some("The game is full, try again later.".into()),
+///         ..default()
+///     })
+///     .add_systems(Update, ban);
+/// ```
+#[derive(Resource, Debug, Clone, Default)]
+pub struct ConnectionLimits {
+    /// The most connections a single IP address can have open.
+    pub max_per_ip: Option<usize>,
+    /// The most connections an IP range can have open between all of its addresses.
+    pub max_per_cidr: Vec<(Cidr, usize)>,
+    /// The most connections the server will have open.
+    pub max_total: Option<usize>,
+    /// The most connections each listener will have open. These replace the
+    /// listeners' own [`ListenerConfig::max_clients`](crate::listener::ListenerConfig::max_clients).
+    pub max_per_listener: HashMap<ListenerId, usize>,
+    /// If not empty, only addresses in these ranges can connect.
+    pub allow: Vec<Cidr>,
+    /// Addresses in these ranges can't connect, even if they're allowed.
+    pub deny: Vec<Cidr>,
+    /// Text sent to a rejected connection before it's closed. Throttled connections
+    /// are closed without it.
+    pub message: Option<String>,
+    /// Text sent instead of [`message`](Self::message) when the server or the
+    /// listener is full.
+    pub full_message: Option<String>,
+    /// How fast new connections are accepted. Connections over the rate are closed
+    /// as soon as they're accepted.
+    pub rate: RateLimits,
+}
+
+/// Limits on how fast new connections are accepted, set through
+/// [`ConnectionLimits::rate`].
+///
+/// ```rust
+/// use bevy_nest::prelude::*;
+///
+/// let limits = ConnectionLimits {
+///     rate: RateLimits {
+///         // Bursts of up to 3 connections, then one every 10 seconds.
+///         per_ip: Some(Rate { burst: 3, per_second: 0.1 }),
+///         global: Some(Rate { burst: 50, per_second: 10.0 }),
+///     },
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RateLimits {
+    /// How fast a single IP address can connect.
+    pub per_ip: Option<Rate>,
+    /// How fast anyone can connect.
+    pub global: Option<Rate>,
+}
+
+/// A token bucket: up to `burst` at once, refilled at `per_second`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rate {
+    pub burst: u32,
+    pub per_second: f64,
+}
+
+/// Why a connection was rejected, sent with [`NetworkEvent::ConnectionRejected`](crate::events::NetworkEvent::ConnectionRejected).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    /// The address is denied, or not allowed.
+    Denied,
+    /// The address has [`max_per_ip`](ConnectionLimits::max_per_ip) connections open.
+    PerIpLimit,
+    /// The range has as many connections open as its [`max_per_cidr`](ConnectionLimits::max_per_cidr) limit.
+    PerCidrLimit(Cidr),
+    /// The server has [`max_total`](ConnectionLimits::max_total) connections open.
+    ServerFull,
+    /// The listener has as many connections open as its
+    /// [`max_per_listener`](ConnectionLimits::max_per_listener) limit or
+    /// [`max_clients`](crate::listener::ListenerConfig::max_clients).
+    ListenerFull,
+    /// The address, or everyone, is connecting faster than the [`RateLimits`] allow.
+    Throttled,
+}
+
+impl ConnectionLimits {
+    // Check a new connection against the limits, given the listener's own limit and
+    // the clients that are already connected.
+    pub(crate) fn check(
+        &self,
+        info: &ClientInfo,
+        max_clients: Option<usize>,
+        connected: &[ClientInfo],
+    ) -> Result<(), RejectReason> {
+        let ip = info.peer_addr.ip();
+
+        // Unix socket clients don't have an address to limit by. Denied addresses are
+        // told so even when the server is full.
+        let denied = !ip.is_unspecified()
+            && (self.deny.iter().any(|cidr| cidr.contains(ip))
+                || (!self.allow.is_empty() && !self.allow.iter().any(|cidr| cidr.contains(ip))));
+
+        if denied {
+            return Err(RejectReason::Denied);
+        }
+
+        if self.max_total.is_some_and(|max| connected.len() >= max) {
+            return Err(RejectReason::ServerFull);
+        }
+
+        let max_clients = self
+            .max_per_listener
+            .get(&info.listener)
+            .copied()
+            .or(max_clients);
+
+        if let Some(max) = max_clients {
+            let on_listener = connected
+                .iter()
+                .filter(|other| other.listener == info.listener)
+                .count();
+
+            if on_listener >= max {
+                return Err(RejectReason::ListenerFull);
+            }
+        }
+
+        let connected = connected
+            .iter()
+            .map(|other| other.peer_addr.ip())
+            .collect::<Vec<_>>();
+
+        if ip.is_unspecified() {
+            return Ok(());
+        }
+
+        if let Some(max) = self.max_per_ip {
+            let same_ip = connected
+                .iter()
+                .filter(|other| other.to_canonical() == ip.to_canonical())
+                .count();
+
+            if same_ip >= max {
+                return Err(RejectReason::PerIpLimit);
+            }
+        }
+
+        for (cidr, max) in &self.max_per_cidr {
+            if !cidr.contains(ip) {
+                continue;
+            }
+
+            let in_range = connected
+                .iter()
+                .filter(|other| cidr.contains(**other))
+                .count();
+
+            if in_range >= *max {
+                return Err(RejectReason::PerCidrLimit(*cidr));
+            }
+        }
+
+        Ok(())
+    }
+
+    // The text to send a connection rejected for the given reason.
+    pub(crate) fn message_for(&self, reason: RejectReason) -> Option<String> {
+        match reason {
+            RejectReason::ServerFull | RejectReason::ListenerFull => {
+                self.full_message.clone().or_else(|| self.message.clone())
+            }
+            RejectReason::Throttled => None,
+            _ => self.message.clone(),
+        }
+    }
+}
+
+/// Limits on how much input a single client can send, set per listener through
+/// [`ListenerConfig::input_limit`](crate::listener::ListenerConfig::input_limit).
+/// Going over either rate counts as flooding, which is reported with
+/// [`NetworkEvent::InputFlood`](crate::events::NetworkEvent::InputFlood) and handled
+/// according to the [`FloodPolicy`].
+///
+/// ```rust
+/// use bevy_nest::prelude::*;
+///
+/// let config = ListenerConfig {
+///     input_limit: Some(InputLimit {
+///         bytes: Some(Rate { burst: 4096, per_second: 1024.0 }),
+///         lines: Some(Rate { burst: 20, per_second: 5.0 }),
+///         policy: FloodPolicy::Drop("Slow down!".into()),
+///     }),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InputLimit {
+    /// How many bytes the client can send.
+    pub bytes: Option<Rate>,
+    /// How many lines of text the client can send.
+    pub lines: Option<Rate>,
+    pub policy: FloodPolicy,
+}
+
+/// What to do with a client that sends more than its [`InputLimit`] allows.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum FloodPolicy {
+    /// Stop reading from the client until it's back under the limit, so its input
+    /// queues up and arrives late instead of all at once.
+    #[default]
+    Delay,
+    /// Throw away input over the limit, sending the client this warning when it
+    /// starts flooding.
+    Drop(String),
+    /// Disconnect the client with [`DisconnectReason::InputFlood`](crate::events::DisconnectReason::InputFlood).
+    Disconnect,
+}
+
+/// Limits on how much output can be queued for a single client that isn't reading
+/// it fast enough, set per listener through
+/// [`ListenerConfig::outbox_limit`](crate::listener::ListenerConfig::outbox_limit).
+/// Going over either limit is reported with
+/// [`NetworkEvent::OutboxOverflow`](crate::events::NetworkEvent::OutboxOverflow) and
+/// handled according to the [`OverflowPolicy`].
+///
+/// ```rust
+/// use bevy_nest::prelude::*;
+///
+/// let config = ListenerConfig {
+///     outbox_limit: Some(OutboxLimit {
+///         max_messages: Some(1000),
+///         max_bytes: Some(256 * 1024),
+///         policy: OverflowPolicy::Disconnect,
+///     }),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OutboxLimit {
+    /// The most messages that can be queued.
+    pub max_messages: Option<usize>,
+    /// The most bytes that can be queued.
+    pub max_bytes: Option<usize>,
+    pub policy: OverflowPolicy,
+}
+
+/// What to do with a message that doesn't fit in a client's outbox.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Throw away the oldest queued messages to make room for it.
+    DropOldest,
+    /// Throw it away.
+    #[default]
+    DropNewest,
+    /// Throw it away and disconnect the client with [`DisconnectReason::SlowConsumer`](crate::events::DisconnectReason::SlowConsumer).
+    Disconnect,
+}
+
+// A bucket of tokens, one taken per connection.
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(rate: &Rate, now: Instant) -> Self {
+        Self {
+            tokens: rate.burst as f64,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, rate: &Rate, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * rate.per_second).min(rate.burst as f64);
+        self.updated = now;
+    }
+}
+
+// How many addresses to track before forgetting the ones with full buckets.
+const MAX_TRACKED_IPS: usize = 4096;
+
+// The rate limits, shared with the listeners so they can throttle connections as
+// they're accepted.
+#[derive(Debug, Default)]
+pub(crate) struct Throttle(Mutex<ThrottleState>);
+
+#[derive(Debug, Default)]
+struct ThrottleState {
+    limits: RateLimits,
+    global: Option<Bucket>,
+    per_ip: HashMap<IpAddr, Bucket>,
+}
+
+impl Throttle {
+    pub(crate) fn configure(&self, limits: RateLimits) {
+        let mut state = self.0.lock().unwrap();
+
+        if state.limits != limits {
+            *state = ThrottleState {
+                limits,
+                ..Default::default()
+            };
+        }
+    }
+
+    // Take a token for a new connection from the address, if there's one to take.
+    pub(crate) fn check(&self, ip: IpAddr) -> Result<(), RejectReason> {
+        let mut state = self.0.lock().unwrap();
+        let ThrottleState {
+            limits,
+            global,
+            per_ip,
+        } = &mut *state;
+
+        let now = Instant::now();
+
+        let mut ip_bucket = None;
+
+        if let Some(rate) = &limits.per_ip {
+            if per_ip.len() >= MAX_TRACKED_IPS {
+                per_ip.retain(|_, bucket| {
+                    bucket.refill(rate, now);
+                    bucket.tokens < rate.burst as f64
+                });
+            }
+
+            let bucket = per_ip
+                .entry(ip.to_canonical())
+                .or_insert_with(|| Bucket::new(rate, now));
+
+            bucket.refill(rate, now);
+            ip_bucket = Some(bucket);
+        }
+
+        let global_bucket = limits.global.as_ref().map(|rate| {
+            let bucket = global.get_or_insert_with(|| Bucket::new(rate, now));
+
+            bucket.refill(rate, now);
+            bucket
+        });
+
+        // Only spend a token if both limits have one, so connections turned away by
+        // one limit don't use up the other.
+        let buckets = [ip_bucket, global_bucket];
+
+        if buckets.iter().flatten().any(|bucket| bucket.tokens < 1.0) {
+            return Err(RejectReason::Throttled);
+        }
+
+        for bucket in buckets.into_iter().flatten() {
+            bucket.tokens -= 1.0;
+        }
+
+        Ok(())
+    }
+}
+
+// A client's input over its limit.
+pub(crate) struct Flood {
+    // Whether the client was under the limit before this.
+    pub(crate) started: bool,
+    // How long to wait before the input is under the limit, for the delay policy.
+    pub(crate) delay: Duration,
+}
+
+// Keeps track of a single client's input against its limit.
+pub(crate) struct InputThrottle {
+    limit: InputLimit,
+    bytes: Option<Bucket>,
+    lines: Option<Bucket>,
+    flooding: bool,
+}
+
+impl InputThrottle {
+    pub(crate) fn new(limit: InputLimit) -> Self {
+        let now = Instant::now();
+
+        Self {
+            bytes: limit.bytes.map(|rate| Bucket::new(&rate, now)),
+            lines: limit.lines.map(|rate| Bucket::new(&rate, now)),
+            limit,
+            flooding: false,
+        }
+    }
+
+    pub(crate) fn policy(&self) -> &FloodPolicy {
+        &self.limit.policy
+    }
+
+    // Take tokens for input read from the client.
+    pub(crate) fn check(&mut self, bytes: usize, lines: usize) -> Result<(), Flood> {
+        let now = Instant::now();
+        let delay = matches!(self.limit.policy, FloodPolicy::Delay);
+
+        let mut over = false;
+        let mut wait = Duration::ZERO;
+
+        for (bucket, rate, amount) in [
+            (&mut self.bytes, self.limit.bytes, bytes),
+            (&mut self.lines, self.limit.lines, lines),
+        ] {
+            let (Some(bucket), Some(rate)) = (bucket, rate) else {
+                continue;
+            };
+
+            bucket.refill(&rate, now);
+
+            let amount = amount as f64;
+
+            if bucket.tokens >= amount {
+                bucket.tokens -= amount;
+                continue;
+            }
+
+            over = true;
+
+            // Delayed input is still let through, so it's paid for up front.
+            if delay {
+                bucket.tokens -= amount;
+
+                let seconds = -bucket.tokens / rate.per_second;
+
+                wait = wait.max(Duration::try_from_secs_f64(seconds).unwrap_or(Duration::MAX));
+            }
+        }
+
+        if !over {
+            self.flooding = false;
+
+            return Ok(());
+        }
+
+        let started = !self.flooding;
+
+        self.flooding = true;
+
+        Err(Flood {
+            started,
+            delay: wait,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::*;
+
+    const A: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const B: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+    const C: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 3));
+
+    fn limited(per_ip: Option<Rate>, global: Option<Rate>) -> Throttle {
+        let throttle = Throttle::default();
+
+        throttle.configure(RateLimits { per_ip, global });
+
+        throttle
+    }
+
+    // A bucket that never refills during a test.
+    fn rate(burst: u32) -> Option<Rate> {
+        Some(Rate {
+            burst,
+            per_second: 0.0,
+        })
+    }
+
+    #[test]
+    fn per_ip_and_global_rates() {
+        let throttle = limited(rate(2), rate(3));
+
+        assert!(throttle.check(A).is_ok());
+        assert!(throttle.check(A).is_ok());
+        assert_eq!(throttle.check(A), Err(RejectReason::Throttled));
+
+        assert!(throttle.check(B).is_ok());
+        assert_eq!(throttle.check(C), Err(RejectReason::Throttled));
+    }
+
+    #[test]
+    fn rejections_spend_no_tokens() {
+        let throttle = limited(rate(1), rate(1));
+
+        assert!(throttle.check(A).is_ok());
+
+        // Turned away by the global rate, so B keeps its own token.
+        assert_eq!(throttle.check(B), Err(RejectReason::Throttled));
+        assert_eq!(throttle.0.lock().unwrap().per_ip[&B].tokens, 1.0);
+
+        let throttle = limited(rate(1), rate(2));
+
+        assert!(throttle.check(A).is_ok());
+
+        // Turned away by its own rate, so the global token is left for others.
+        assert_eq!(throttle.check(A), Err(RejectReason::Throttled));
+        assert!(throttle.check(B).is_ok());
+    }
+
+    #[test]
+    fn forgets_addresses_with_full_buckets() {
+        let addr = |n: usize| IpAddr::V4(Ipv4Addr::from(0x0A00_0000 + n as u32));
+
+        // Addresses still being throttled are remembered.
+        let throttle = limited(rate(1), None);
+
+        for n in 0..=MAX_TRACKED_IPS {
+            assert!(throttle.check(addr(n)).is_ok());
+        }
+
+        assert_eq!(throttle.0.lock().unwrap().per_ip.len(), MAX_TRACKED_IPS + 1);
+        assert_eq!(throttle.check(addr(0)), Err(RejectReason::Throttled));
+
+        // Ones that have refilled are forgotten once there are too many.
+        let throttle = limited(
+            Some(Rate {
+                burst: 1,
+                per_second: 1_000_000.0,
+            }),
+            None,
+        );
+
+        for n in 0..MAX_TRACKED_IPS {
+            assert!(throttle.check(addr(n)).is_ok());
+        }
+
+        std::thread::sleep(Duration::from_millis(10));
+
+        assert!(throttle.check(addr(MAX_TRACKED_IPS)).is_ok());
+        assert_eq!(throttle.0.lock().unwrap().per_ip.len(), 1);
+    }
+}
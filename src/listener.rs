@@ -0,0 +1,321 @@
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    time::Duration,
+};
+
+#[cfg(unix)]
+use std::{
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use uuid::Uuid;
+
+#[cfg(unix)]
+use crate::copyover::set_inheritable;
+use crate::{
+    cidr::Cidr,
+    limits::{InputLimit, OutboxLimit},
+    transport::Transport,
+};
+
+// The first file descriptor passed by systemd socket activation.
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+// Whether the sockets passed by systemd have been taken already.
+#[cfg(unix)]
+static SD_LISTEN_FDS_TAKEN: AtomicBool = AtomicBool::new(false);
+
+// The namespace the ids of named listeners are made in.
+const LISTENER_NAMESPACE: Uuid = Uuid::from_u128(0x6f1c_2a4e_93b7_4d5a_8e21_0c7f_b64d_19a3);
+
+// Unix socket clients have no IP address, so this stands in for theirs.
+pub(crate) const UNIX_SOCKET_ADDR: SocketAddr =
+    SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0));
+
+/// A unique identifier for a listener, returned by [`Server::listen`](crate::server::Server::listen).
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
+pub struct ListenerId(pub(crate) Uuid);
+
+impl ListenerId {
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+
+    /// The id of the listener with the given [`ListenerConfig::name`], which is the
+    /// same in every process.
+    pub fn named(name: &str) -> Self {
+        Self(Uuid::new_v5(&LISTENER_NAMESPACE, name.as_bytes()))
+    }
+}
+
+impl Default for ListenerId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Settings for a single listener, passed to [`Server::listen_with`](crate::server::Server::listen_with).
+///
+/// ```rust
+/// use bevy_nest::prelude::*;
+///
+/// let config = ListenerConfig {
+///     greeting: Some("Welcome, builder!".into()),
+///     negotiate: vec![(WILL, GMCP)],
+///     max_clients: Some(10),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone)]
+pub struct ListenerConfig {
+    /// A name that gives the listener the same [`ListenerId`] in every process, see
+    /// [`ListenerId::named`]. Clients handed over by a
+    /// [`copyover`](crate::server::Server::copyover) come back to the listener with
+    /// the same name in the new process, and its config applies to them again.
+    /// Starting a listener with the name of one that's still running replaces it.
+    pub name: Option<String>,
+    /// Text sent to every client as soon as it connects.
+    pub greeting: Option<String>,
+    /// Telnet negotiations sent to every client as soon as it connects, before the
+    /// greeting. Each entry is a verb ([`WILL`](crate::telnet::WILL), [`DO`](crate::telnet::DO), ...)
+    /// and the option it applies to.
+    pub negotiate: Vec<(u8, u8)>,
+    /// The maximum number of clients connected through this listener at once.
+    /// Connections over the limit are rejected like those over the
+    /// [`ConnectionLimits`](crate::limits::ConnectionLimits), which can also change
+    /// the limit at runtime.
+    pub max_clients: Option<usize>,
+    /// Expect a [PROXY protocol](https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt)
+    /// header at the start of every connection, e.g. when the listener sits behind
+    /// HAProxy, and use the addresses in it as the client's.
+    pub proxy_protocol: Option<ProxyProtocol>,
+    /// How long a new connection has to finish its handshake, like sending its
+    /// PROXY header, negotiating TLS or upgrading to a WebSocket, before it's closed.
+    /// Until then it isn't a client, so none of the other limits apply to it.
+    /// Defaults to 10 seconds.
+    pub handshake_timeout: Option<Duration>,
+    /// How much input each client can send. See [`InputLimit`].
+    pub input_limit: Option<InputLimit>,
+    /// How much output can be queued for each client. See [`OutboxLimit`].
+    pub outbox_limit: Option<OutboxLimit>,
+    /// Disconnect clients that go quiet. See [`IdleTimeout`].
+    pub idle_timeout: Option<IdleTimeout>,
+    /// Disconnect clients that haven't finished telnet negotiation this long after
+    /// connecting, like scanners that connect and never send anything. Negotiation
+    /// is finished once the client has answered everything in
+    /// [`negotiate`](Self::negotiate), or sent a line of text.
+    pub negotiation_timeout: Option<Duration>,
+}
+
+impl Default for ListenerConfig {
+    fn default() -> Self {
+        Self {
+            name: None,
+            greeting: None,
+            negotiate: Vec::new(),
+            max_clients: None,
+            proxy_protocol: None,
+            handshake_timeout: Some(Duration::from_secs(10)),
+            input_limit: None,
+            outbox_limit: None,
+            idle_timeout: None,
+            negotiation_timeout: None,
+        }
+    }
+}
+
+/// How long a client can go without sending a line of text before it's
+/// disconnected. Telnet commands, like keepalives, don't count.
+///
+/// ```rust
+/// use std::time::Duration;
+///
+/// use bevy_nest::prelude::*;
+///
+/// let config = ListenerConfig {
+///     idle_timeout: Some(IdleTimeout {
+///         timeout: Duration::from_secs(30 * 60),
+///         warning: Some((Duration::from_secs(60), "Still there?".into())),
+///     }),
+///     negotiation_timeout: Some(Duration::from_secs(10)),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdleTimeout {
+    pub timeout: Duration,
+    /// Text sent to the client this long before it's disconnected.
+    pub warning: Option<(Duration, String)>,
+}
+
+/// PROXY protocol settings for a listener. Both v1 (text) and v2 (binary) headers
+/// are understood. Connections that don't start with a valid header are closed
+/// before telnet negotiation begins.
+///
+/// ```rust
+/// use bevy_nest::prelude::*;
+///
+/// let config = ListenerConfig {
+///     proxy_protocol: Some(ProxyProtocol {
+///         trusted: vec!["10.0.0.0/8".parse().unwrap()],
+///     }),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone)]
+pub struct ProxyProtocol {
+    /// The proxies allowed to connect. Connections from anywhere else are closed
+    /// without reading a header, so clients can't bypass the proxy and spoof their
+    /// address. If empty, every connection is closed. Connections to a Unix socket
+    /// listener are always trusted, since the socket file's permissions decide who
+    /// can connect.
+    pub trusted: Vec<Cidr>,
+}
+
+impl ProxyProtocol {
+    // Whether a connection from the given address may send a header.
+    pub(crate) fn trusts(&self, addr: SocketAddr) -> bool {
+        addr == UNIX_SOCKET_ADDR || self.trusted.iter().any(|cidr| cidr.contains(addr.ip()))
+    }
+}
+
+// A connection accepted by a listener.
+pub(crate) struct Accepted {
+    pub(crate) stream: Box<dyn Transport>,
+    // The connection's socket, kept so it can be handed over in a copyover.
+    #[cfg(unix)]
+    pub(crate) fd: RawFd,
+    pub(crate) peer_addr: SocketAddr,
+    pub(crate) local_addr: SocketAddr,
+}
+
+// A bound socket that accepts connections.
+pub(crate) enum BoundListener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl BoundListener {
+    // Wrap an already bound TCP or Unix domain socket listener.
+    #[cfg(unix)]
+    pub(crate) fn from_fd(fd: OwnedFd) -> io::Result<Self> {
+        let listener = std::net::TcpListener::from(fd);
+
+        // Only sockets with an IP address have a TCP local address.
+        if listener.local_addr().is_ok() {
+            listener.set_nonblocking(true)?;
+
+            return TcpListener::from_std(listener).map(BoundListener::Tcp);
+        }
+
+        let listener = std::os::unix::net::UnixListener::from(OwnedFd::from(listener));
+
+        listener.local_addr()?;
+        listener.set_nonblocking(true)?;
+
+        UnixListener::from_std(listener).map(BoundListener::Unix)
+    }
+
+    pub(crate) fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            BoundListener::Tcp(listener) => listener.local_addr(),
+            #[cfg(unix)]
+            BoundListener::Unix(_) => Ok(UNIX_SOCKET_ADDR),
+        }
+    }
+
+    // Wait for a new connection.
+    pub(crate) async fn accept(&self) -> io::Result<Accepted> {
+        match self {
+            BoundListener::Tcp(listener) => {
+                let (stream, peer_addr) = listener.accept().await?;
+                let local_addr = stream.local_addr()?;
+
+                Ok(Accepted {
+                    #[cfg(unix)]
+                    fd: stream.as_raw_fd(),
+                    stream: Box::new(stream),
+                    peer_addr,
+                    local_addr,
+                })
+            }
+            #[cfg(unix)]
+            BoundListener::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+
+                Ok(Accepted {
+                    fd: stream.as_raw_fd(),
+                    stream: Box::new(stream),
+                    peer_addr: UNIX_SOCKET_ADDR,
+                    local_addr: UNIX_SOCKET_ADDR,
+                })
+            }
+        }
+    }
+}
+
+// Take the listening sockets passed to this process by systemd socket activation.
+// See: https://www.freedesktop.org/software/systemd/man/latest/sd_listen_fds.html
+//
+// The variables are left as they are. Changing the environment with other threads
+// running isn't safe, processes the game spawns ignore them since `LISTEN_PID` isn't
+// theirs, and they let the process take the sockets again after a copyover.
+#[cfg(unix)]
+pub(crate) fn take_systemd_fds() -> Vec<OwnedFd> {
+    let for_us = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+
+    let count = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse::<RawFd>().ok())
+        .unwrap_or(0);
+
+    if !for_us || count <= 0 || SD_LISTEN_FDS_TAKEN.swap(true, Ordering::SeqCst) {
+        return Vec::new();
+    }
+
+    (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count)
+        // After a copyover, the listeners that were stopped are gone, and their fds
+        // may have been reused.
+        .filter(|fd| is_listening(*fd))
+        .map(|fd| {
+            // Only a copyover hands them on.
+            if let Err(err) = set_inheritable(fd, false) {
+                bevy::log::warn!("Could not set close-on-exec for systemd socket {fd}: {err}");
+            }
+
+            // SAFETY: systemd passed these to this process (LISTEN_PID matches), or it
+            // had them before a copyover, and they're only ever taken once.
+            unsafe { OwnedFd::from_raw_fd(fd) }
+        })
+        .collect()
+}
+
+// Whether an fd is a socket listening for connections.
+#[cfg(unix)]
+fn is_listening(fd: RawFd) -> bool {
+    let mut listening: libc::c_int = 0;
+    let mut length = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+
+    // SAFETY: getsockopt only writes an int to the buffer it's given.
+    let result = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_ACCEPTCONN,
+            (&mut listening as *mut libc::c_int).cast(),
+            &mut length,
+        )
+    };
+
+    result == 0 && listening != 0
+}
@@ -0,0 +1,35 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counts of what's happened to connections since the server started, from
+/// [`Server::metrics`](crate::server::Server::metrics).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Metrics {
+    /// Connections accepted by the listeners, whether or not they were let in.
+    pub connections_accepted: u64,
+    /// Connections closed for breaking the [`ConnectionLimits`](crate::limits::ConnectionLimits).
+    pub connections_rejected: u64,
+    /// Connections closed for coming in faster than the [`RateLimits`](crate::limits::RateLimits) allow.
+    pub connections_throttled: u64,
+}
+
+// The live counters behind the metrics, shared with the server's tasks.
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    pub(crate) connections_accepted: AtomicU64,
+    pub(crate) connections_rejected: AtomicU64,
+    pub(crate) connections_throttled: AtomicU64,
+}
+
+impl Counters {
+    pub(crate) fn snapshot(&self) -> Metrics {
+        Metrics {
+            connections_accepted: self.connections_accepted.load(Ordering::Relaxed),
+            connections_rejected: self.connections_rejected.load(Ordering::Relaxed),
+            connections_throttled: self.connections_throttled.load(Ordering::Relaxed),
+        }
+    }
+}
+
+pub(crate) fn increment(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
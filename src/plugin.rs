@@ -1,26 +1,188 @@
-use bevy::prelude::*;
+use bevy::{
+    ecs::{intern::Interned, schedule::ScheduleLabel},
+    log::Level,
+    prelude::*,
+};
+use tokio::runtime::Handle;
 
 use crate::{
     events::{Inbox, NetworkEvent, Outbox},
+    limits::ConnectionLimits,
+    listener::ListenerConfig,
     server::Server,
-    systems::{handle_events, handle_inbox, handle_incoming, handle_lost, handle_outbox},
+    systems::{handle_incoming, handle_lost, handle_outbox, handle_received},
 };
 
-pub struct NestPlugin;
+/// The systems that move data between the network and Bevy, for ordering game
+/// systems around them.
+///
+/// Within [`Receive`](Self::Receive), new connections are set up first, then
+/// [`NetworkEvent`]s and [`Inbox`] messages are sent, and disconnected clients are
+/// removed last. A client's messages are never sent before the update it connected
+/// in has seen its [`NetworkEvent::Connected`], or after its
+/// [`NetworkEvent::Disconnected`].
+///
+/// ```rust
+/// use bevy::prelude::*;
+/// use bevy_nest::prelude::*;
+///
+/// fn log_in(mut events: EventReader<NetworkEvent>) {
+///     // ...
+/// }
+///
+/// // Flush output once per game tick, rather than every frame.
+/// App::new()
+///     .add_plugins(NestPlugin::default().schedules(PreUpdate, FixedPostUpdate))
+///     .add_systems(PreUpdate, log_in.after(NestSet::Receive));
+/// ```
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NestSet {
+    /// Receive connections, events and messages from the network.
+    Receive,
+    /// Send [`Outbox`] messages to the network.
+    Send,
+}
+
+// The schedules the plugin's sets were added to, for plugins that build on it.
+#[derive(Resource)]
+pub(crate) struct NestSchedules {
+    pub(crate) receive: Interned<dyn ScheduleLabel>,
+}
+
+/// Adds the [`Server`] resource and the systems that connect it to Bevy.
+///
+/// The defaults work for most games, and can be tuned with the builder methods:
+///
+/// ```rust
+/// use bevy::{log::Level, prelude::*};
+/// use bevy_nest::prelude::*;
+///
+/// App::new().add_plugins(
+///     NestPlugin::default()
+///         .read_buffer_size(4096)
+///         .worker_threads(2)
+///         .negotiate(WILL, GMCP)
+///         .log_level(Level::DEBUG),
+/// );
+/// ```
+pub struct NestPlugin {
+    pub(crate) read_buffer_size: usize,
+    pub(crate) worker_threads: Option<usize>,
+    pub(crate) runtime_handle: Option<Handle>,
+    pub(crate) channel_capacity: Option<usize>,
+    pub(crate) listener_defaults: ListenerConfig,
+    pub(crate) log_level: Level,
+    receive_schedule: Interned<dyn ScheduleLabel>,
+    send_schedule: Interned<dyn ScheduleLabel>,
+}
+
+impl Default for NestPlugin {
+    fn default() -> Self {
+        Self {
+            read_buffer_size: 1024,
+            worker_threads: None,
+            runtime_handle: None,
+            channel_capacity: None,
+            listener_defaults: ListenerConfig::default(),
+            log_level: Level::INFO,
+            receive_schedule: PreUpdate.intern(),
+            send_schedule: Last.intern(),
+        }
+    }
+}
+
+impl NestPlugin {
+    /// How many bytes are read from a client at a time. Defaults to 1024.
+    pub fn read_buffer_size(mut self, size: usize) -> Self {
+        self.read_buffer_size = size;
+        self
+    }
+
+    /// How many threads the network runtime uses. Defaults to one per CPU core.
+    pub fn worker_threads(mut self, threads: usize) -> Self {
+        self.worker_threads = Some(threads);
+        self
+    }
+
+    /// Run the network on a tokio runtime the app already has, instead of building
+    /// one. The runtime needs IO and time enabled, and has to keep running on its own
+    /// threads, so a multi-thread runtime is the usual choice. When it's set,
+    /// [`worker_threads`](Self::worker_threads) is ignored.
+    ///
+    /// ```rust
+    /// use bevy::prelude::*;
+    /// use bevy_nest::prelude::*;
+    ///
+    /// let runtime = tokio::runtime::Runtime::new().unwrap();
+    ///
+    /// App::new().add_plugins(NestPlugin::default().runtime_handle(runtime.handle().clone()));
+    /// ```
+    pub fn runtime_handle(mut self, handle: Handle) -> Self {
+        self.runtime_handle = Some(handle);
+        self
+    }
+
+    /// How many messages from clients can wait between the network and the next
+    /// update. When there's no room, the network waits for the next update before
+    /// reading more from clients, without blocking any threads. Unbounded by default.
+    pub fn channel_capacity(mut self, capacity: usize) -> Self {
+        self.channel_capacity = Some(capacity);
+        self
+    }
+
+    /// The [`ListenerConfig`] used by listeners that aren't given one, like
+    /// [`Server::listen`], and by [`Server::accept_connection`].
+    pub fn listener_defaults(mut self, config: ListenerConfig) -> Self {
+        self.listener_defaults = config;
+        self
+    }
+
+    /// Add a telnet negotiation to the [`listener_defaults`](Self::listener_defaults),
+    /// sent to every client as soon as it connects.
+    pub fn negotiate(mut self, verb: u8, option: u8) -> Self {
+        self.listener_defaults.negotiate.push((verb, option));
+        self
+    }
+
+    /// The level routine network activity is logged at, like connections,
+    /// disconnections and every message received. Defaults to [`Level::INFO`], so
+    /// use [`Level::DEBUG`] or [`Level::TRACE`] to keep it out of the way.
+    /// Warnings and errors are always logged as such.
+    pub fn log_level(mut self, level: Level) -> Self {
+        self.log_level = level;
+        self
+    }
+
+    /// The schedules the plugin receives from and sends to the network in, which are
+    /// where [`NestSet::Receive`] and [`NestSet::Send`] run. Defaults to [`PreUpdate`]
+    /// and [`Last`].
+    pub fn schedules(mut self, receive: impl ScheduleLabel, send: impl ScheduleLabel) -> Self {
+        self.receive_schedule = receive.intern();
+        self.send_schedule = send.intern();
+        self
+    }
+}
 
 impl Plugin for NestPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(Server::new());
+        app.insert_resource(Server::new(self));
+        app.init_resource::<ConnectionLimits>();
 
         app.add_event::<NetworkEvent>();
         app.add_event::<Inbox>();
         app.add_event::<Outbox>();
 
+        app.insert_resource(NestSchedules {
+            receive: self.receive_schedule,
+        });
+
         app.add_systems(
-            PreUpdate,
-            (handle_incoming, handle_lost, handle_events, handle_inbox),
+            self.receive_schedule,
+            (handle_incoming, handle_received, handle_lost)
+                .chain()
+                .in_set(NestSet::Receive),
         );
 
-        app.add_systems(Last, handle_outbox);
+        app.add_systems(self.send_schedule, handle_outbox.in_set(NestSet::Send));
     }
 }
@@ -1,2 +1,5 @@
 #[doc(hidden)]
-pub use crate::{errors::*, events::*, plugin::*, server::*, telnet::*};
+pub use crate::{
+    cidr::*, errors::*, events::*, limits::*, listener::*, metrics::*, plugin::*, server::*,
+    telnet::*, transport::*,
+};
@@ -0,0 +1,343 @@
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+// See: https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+// A v1 header is at most 107 bytes, including the trailing CRLF.
+const MAX_V1_LENGTH: usize = 107;
+
+// Read a PROXY protocol v1 or v2 header from the start of a stream, returning the
+// source and destination addresses it carries. Headers without addresses, like
+// `PROXY UNKNOWN` or v2 `LOCAL` health checks, return `None`, meaning the
+// connection's own addresses should be used.
+pub(crate) async fn read_header(
+    stream: &mut (impl AsyncRead + Unpin),
+) -> io::Result<Option<(SocketAddr, SocketAddr)>> {
+    match stream.read_u8().await? {
+        b'P' => read_v1(stream).await,
+        b'\r' => read_v2(stream).await,
+        _ => Err(invalid("Missing PROXY header")),
+    }
+}
+
+async fn read_v1(
+    stream: &mut (impl AsyncRead + Unpin),
+) -> io::Result<Option<(SocketAddr, SocketAddr)>> {
+    let mut line = vec![b'P'];
+
+    // Read a byte at a time so nothing after the header is consumed.
+    while !line.ends_with(b"\r\n") {
+        if line.len() == MAX_V1_LENGTH {
+            return Err(invalid("PROXY header is too long"));
+        }
+
+        line.push(stream.read_u8().await?);
+    }
+
+    parse_v1(&line[..line.len() - 2])
+}
+
+fn parse_v1(line: &[u8]) -> io::Result<Option<(SocketAddr, SocketAddr)>> {
+    let line = std::str::from_utf8(line).map_err(|_| invalid("PROXY header isn't ASCII"))?;
+    let mut parts = line.split(' ');
+
+    if parts.next() != Some("PROXY") {
+        return Err(invalid("Missing PROXY header"));
+    }
+
+    match parts.next() {
+        Some("TCP4") | Some("TCP6") => {}
+        Some("UNKNOWN") => return Ok(None),
+        _ => return Err(invalid("Unsupported PROXY protocol family")),
+    }
+
+    let fields: Vec<&str> = parts.collect();
+
+    let [source, destination, source_port, destination_port] = fields[..] else {
+        return Err(invalid("Malformed PROXY header"));
+    };
+
+    let address = |ip: &str, port: &str| -> io::Result<SocketAddr> {
+        let ip = ip.parse().map_err(|_| invalid("Invalid PROXY address"))?;
+        let port = port.parse().map_err(|_| invalid("Invalid PROXY port"))?;
+
+        Ok(SocketAddr::new(ip, port))
+    };
+
+    Ok(Some((
+        address(source, source_port)?,
+        address(destination, destination_port)?,
+    )))
+}
+
+async fn read_v2(
+    stream: &mut (impl AsyncRead + Unpin),
+) -> io::Result<Option<(SocketAddr, SocketAddr)>> {
+    // The rest of the signature, the version and command, the address family and
+    // the length of what follows.
+    let mut header = [0; 15];
+
+    stream.read_exact(&mut header).await?;
+
+    if header[..11] != V2_SIGNATURE[1..] {
+        return Err(invalid("Missing PROXY header"));
+    }
+
+    let version_command = header[11];
+    let family = header[12];
+    let length = u16::from_be_bytes([header[13], header[14]]) as usize;
+
+    if version_command >> 4 != 2 {
+        return Err(invalid("Unsupported PROXY protocol version"));
+    }
+
+    let mut body = vec![0; length];
+
+    stream.read_exact(&mut body).await?;
+
+    match version_command & 0x0F {
+        // LOCAL: the proxy's own connection, e.g. a health check.
+        0x0 => return Ok(None),
+        // PROXY: a relayed connection.
+        0x1 => {}
+        _ => return Err(invalid("Unsupported PROXY command")),
+    }
+
+    match family >> 4 {
+        // AF_INET
+        0x1 if body.len() >= 12 => {
+            let source = Ipv4Addr::new(body[0], body[1], body[2], body[3]);
+            let destination = Ipv4Addr::new(body[4], body[5], body[6], body[7]);
+
+            Ok(Some((
+                SocketAddr::new(source.into(), u16::from_be_bytes([body[8], body[9]])),
+                SocketAddr::new(destination.into(), u16::from_be_bytes([body[10], body[11]])),
+            )))
+        }
+        // AF_INET6
+        0x2 if body.len() >= 36 => {
+            let source = Ipv6Addr::from(<[u8; 16]>::try_from(&body[..16]).unwrap());
+            let destination = Ipv6Addr::from(<[u8; 16]>::try_from(&body[16..32]).unwrap());
+
+            Ok(Some((
+                SocketAddr::new(source.into(), u16::from_be_bytes([body[32], body[33]])),
+                SocketAddr::new(destination.into(), u16::from_be_bytes([body[34], body[35]])),
+            )))
+        }
+        // AF_UNSPEC and AF_UNIX carry no IP addresses.
+        0x0 | 0x3 => Ok(None),
+        _ => Err(invalid("Malformed PROXY header")),
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, net::SocketAddr};
+
+    use super::{read_header, V2_SIGNATURE};
+
+    type Addrs = Option<(SocketAddr, SocketAddr)>;
+
+    // Read a header, returning what it carried and what's left of the input.
+    fn read(mut input: &[u8]) -> (io::Result<Addrs>, &[u8]) {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        let result = runtime.block_on(read_header(&mut input));
+
+        (result, input)
+    }
+
+    fn v2(version_command: u8, family: u8, body: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+
+        header.push(version_command);
+        header.push(family);
+        header.extend((body.len() as u16).to_be_bytes());
+        header.extend(body);
+
+        header
+    }
+
+    fn addrs(source: &str, destination: &str) -> Addrs {
+        Some((source.parse().unwrap(), destination.parse().unwrap()))
+    }
+
+    #[test]
+    fn v1_tcp4() {
+        let (result, rest) = read(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 23\r\nlook");
+
+        assert_eq!(result.unwrap(), addrs("192.0.2.1:56324", "198.51.100.1:23"));
+        assert_eq!(rest, b"look");
+    }
+
+    #[test]
+    fn v1_tcp6() {
+        let (result, rest) = read(b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 23\r\nlook");
+
+        assert_eq!(
+            result.unwrap(),
+            addrs("[2001:db8::1]:56324", "[2001:db8::2]:23")
+        );
+        assert_eq!(rest, b"look");
+    }
+
+    #[test]
+    fn v1_unknown() {
+        let (result, rest) = read(b"PROXY UNKNOWN\r\nlook");
+
+        assert_eq!(result.unwrap(), None);
+        assert_eq!(rest, b"look");
+    }
+
+    #[test]
+    fn v1_length_limit() {
+        // 107 bytes, including the CRLF, is the longest a header can be.
+        let longest = format!("PROXY UNKNOWN {}\r\n", "x".repeat(91));
+
+        assert_eq!(longest.len(), 107);
+        assert_eq!(read(longest.as_bytes()).0.unwrap(), None);
+
+        let too_long = format!("PROXY UNKNOWN {}\r\n", "x".repeat(92));
+        let (result, rest) = read(too_long.as_bytes());
+
+        // Reading stops at the limit.
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(rest, b"\n");
+    }
+
+    #[test]
+    fn v1_malformed() {
+        for header in [
+            &b"PROXY TCP4 192.0.2.1 198.51.100.1 56324\r\n"[..],
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 99999\r\n",
+            b"PROXY TCP4 nowhere 198.51.100.1 56324 23\r\n",
+            b"PROXY UDP4 192.0.2.1 198.51.100.1 56324 23\r\n",
+            b"PROXIED TCP4 192.0.2.1 198.51.100.1 56324 23\r\n",
+            b"look\r\n",
+        ] {
+            assert_eq!(
+                read(header).0.unwrap_err().kind(),
+                io::ErrorKind::InvalidData
+            );
+        }
+    }
+
+    #[test]
+    fn v2_inet() {
+        let mut input = v2(
+            0x21,
+            0x11,
+            &[192, 0, 2, 1, 198, 51, 100, 1, 0xDC, 0x04, 0, 23],
+        );
+
+        input.extend(b"look");
+
+        let (result, rest) = read(&input);
+
+        assert_eq!(result.unwrap(), addrs("192.0.2.1:56324", "198.51.100.1:23"));
+        assert_eq!(rest, b"look");
+    }
+
+    #[test]
+    fn v2_inet6() {
+        let source: std::net::Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let destination: std::net::Ipv6Addr = "2001:db8::2".parse().unwrap();
+
+        let mut body = [source.octets(), destination.octets()].concat();
+
+        body.extend([0xDC, 0x04, 0, 23]);
+
+        // Any TLVs after the addresses are skipped.
+        body.extend([0x04, 0, 1, 0]);
+
+        let mut input = v2(0x21, 0x21, &body);
+
+        input.extend(b"look");
+
+        let (result, rest) = read(&input);
+
+        assert_eq!(
+            result.unwrap(),
+            addrs("[2001:db8::1]:56324", "[2001:db8::2]:23")
+        );
+        assert_eq!(rest, b"look");
+    }
+
+    #[test]
+    fn v2_local() {
+        let mut input = v2(0x20, 0x11, &[0; 12]);
+
+        input.extend(b"look");
+
+        let (result, rest) = read(&input);
+
+        assert_eq!(result.unwrap(), None);
+        assert_eq!(rest, b"look");
+    }
+
+    #[test]
+    fn v2_unspec() {
+        let mut input = v2(0x21, 0x00, &[]);
+
+        input.extend(b"look");
+
+        let (result, rest) = read(&input);
+
+        assert_eq!(result.unwrap(), None);
+        assert_eq!(rest, b"look");
+    }
+
+    #[test]
+    fn v2_bad_signature() {
+        let mut input = v2(0x21, 0x11, &[0; 12]);
+
+        input[8] = b'X';
+
+        assert_eq!(
+            read(&input).0.unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn v2_bad_version() {
+        let input = v2(0x11, 0x11, &[0; 12]);
+
+        assert_eq!(
+            read(&input).0.unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn v2_short_body() {
+        // Too short for the addresses of its family.
+        let input = v2(0x21, 0x11, &[0; 8]);
+
+        assert_eq!(
+            read(&input).0.unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        // Shorter than its length says.
+        let mut input = v2(0x21, 0x11, &[0; 12]);
+
+        input.truncate(input.len() - 4);
+
+        assert_eq!(
+            read(&input).0.unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+}
@@ -0,0 +1,296 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::Notify;
+
+use crate::{
+    events::Message,
+    limits::{OutboxLimit, OverflowPolicy},
+    server::QueuedOutput,
+    telnet::*,
+};
+
+// A message that didn't fit in a client's outbox.
+pub(crate) struct Overflow {
+    // Whether the outbox had room before this.
+    pub(crate) started: bool,
+    // Whether the client should be disconnected for it.
+    pub(crate) disconnect: bool,
+}
+
+// Encoded output waiting to be written to a client, shared between the server and the
+// client's write task.
+pub(crate) struct OutboxQueue {
+    limit: Option<OutboxLimit>,
+    state: Mutex<QueueState>,
+    notify: Notify,
+}
+
+#[derive(Default)]
+struct QueueState {
+    messages: VecDeque<Arc<[u8]>>,
+    bytes: usize,
+    overflowing: bool,
+    closed: bool,
+}
+
+impl QueueState {
+    fn fits(&self, limit: &OutboxLimit, bytes: usize) -> bool {
+        limit
+            .max_messages
+            .is_none_or(|max| self.messages.len() < max)
+            && limit.max_bytes.is_none_or(|max| self.bytes + bytes <= max)
+    }
+
+    fn pop(&mut self) -> Option<Arc<[u8]>> {
+        let bytes = self.messages.pop_front()?;
+
+        self.bytes -= bytes.len();
+
+        Some(bytes)
+    }
+}
+
+impl OutboxQueue {
+    pub(crate) fn new(limit: Option<OutboxLimit>) -> Self {
+        Self {
+            limit,
+            state: Mutex::new(QueueState::default()),
+            notify: Notify::new(),
+        }
+    }
+
+    // Queue an encoded message for the write task, making room for it according to
+    // the overflow policy if the outbox is full. The bytes can be shared with other
+    // clients' outboxes.
+    pub(crate) fn push(&self, bytes: Arc<[u8]>) -> Result<(), Overflow> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.closed {
+            return Ok(());
+        }
+
+        let mut overflowed = false;
+
+        if let Some(limit) = &self.limit {
+            if !state.fits(limit, bytes.len()) {
+                overflowed = true;
+
+                // Nothing is worth dropping for a message too big to ever fit.
+                let fits_alone = limit.max_bytes.is_none_or(|max| bytes.len() <= max);
+
+                if limit.policy == OverflowPolicy::DropOldest && fits_alone {
+                    while !state.fits(limit, bytes.len()) && state.pop().is_some() {}
+                }
+
+                // There's no room, or it's too big to ever fit.
+                if !state.fits(limit, bytes.len()) {
+                    return Err(overflow(&mut state, limit));
+                }
+            }
+        }
+
+        state.bytes += bytes.len();
+        state.messages.push_back(bytes);
+
+        let result = match &self.limit {
+            Some(limit) if overflowed => Err(overflow(&mut state, limit)),
+            _ => {
+                state.overflowing = false;
+                Ok(())
+            }
+        };
+
+        drop(state);
+
+        self.notify.notify_one();
+
+        result
+    }
+
+    // Wait for the next message to write. Returns `None` once the queue is closed
+    // and everything in it has been written.
+    pub(crate) async fn pop(&self) -> Option<Arc<[u8]>> {
+        loop {
+            let notified = self.notify.notified();
+
+            {
+                let mut state = self.state.lock().unwrap();
+
+                if let Some(bytes) = state.pop() {
+                    return Some(bytes);
+                }
+
+                if state.closed {
+                    return None;
+                }
+            }
+
+            notified.await;
+        }
+    }
+
+    // Stop taking messages, letting the write task finish once the rest are written.
+    pub(crate) fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.notify.notify_one();
+    }
+
+    pub(crate) fn queued(&self) -> QueuedOutput {
+        let state = self.state.lock().unwrap();
+
+        QueuedOutput {
+            messages: state.messages.len(),
+            bytes: state.bytes,
+        }
+    }
+}
+
+fn overflow(state: &mut QueueState, limit: &OutboxLimit) -> Overflow {
+    let started = !state.overflowing;
+
+    state.overflowing = true;
+
+    Overflow {
+        started,
+        disconnect: limit.policy == OverflowPolicy::Disconnect,
+    }
+}
+
+// Turn a message into the bytes sent to the client.
+pub(crate) fn encode(message: &Message) -> Arc<[u8]> {
+    match message {
+        Message::Text(text) => format!("{text}\r\n").into_bytes().into(),
+        Message::Command(command) => command.as_slice().into(),
+        Message::GMCP(payload) => {
+            let mut seq = vec![IAC, SB, GMCP];
+
+            seq.extend(payload.package.as_bytes());
+
+            if let Some(subpackage) = &payload.subpackage {
+                seq.push(b'.');
+                seq.extend(subpackage.as_bytes());
+            }
+
+            if let Some(data) = &payload.data {
+                seq.push(b' ');
+                seq.extend(data.as_bytes());
+            }
+
+            seq.extend(vec![IAC, SE]);
+
+            seq.into()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(
+        max_messages: Option<usize>,
+        max_bytes: Option<usize>,
+        policy: OverflowPolicy,
+    ) -> OutboxQueue {
+        OutboxQueue::new(Some(OutboxLimit {
+            max_messages,
+            max_bytes,
+            policy,
+        }))
+    }
+
+    fn push(queue: &OutboxQueue, text: &str) -> Result<(), (bool, bool)> {
+        queue
+            .push(text.as_bytes().into())
+            .map_err(|overflow| (overflow.started, overflow.disconnect))
+    }
+
+    fn queued(queue: &OutboxQueue) -> Vec<String> {
+        let state = queue.state.lock().unwrap();
+
+        state
+            .messages
+            .iter()
+            .map(|bytes| String::from_utf8(bytes.to_vec()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn drop_oldest() {
+        let queue = queue(Some(2), None, OverflowPolicy::DropOldest);
+
+        assert_eq!(push(&queue, "a"), Ok(()));
+        assert_eq!(push(&queue, "b"), Ok(()));
+        assert_eq!(push(&queue, "c"), Err((true, false)));
+        assert_eq!(queued(&queue), ["b", "c"]);
+    }
+
+    #[test]
+    fn drop_newest() {
+        let queue = queue(Some(2), None, OverflowPolicy::DropNewest);
+
+        assert_eq!(push(&queue, "a"), Ok(()));
+        assert_eq!(push(&queue, "b"), Ok(()));
+        assert_eq!(push(&queue, "c"), Err((true, false)));
+        assert_eq!(queued(&queue), ["a", "b"]);
+    }
+
+    #[test]
+    fn disconnect() {
+        let queue = queue(None, Some(4), OverflowPolicy::Disconnect);
+
+        assert_eq!(push(&queue, "abc"), Ok(()));
+        assert_eq!(push(&queue, "de"), Err((true, true)));
+        assert_eq!(queued(&queue), ["abc"]);
+        assert_eq!(
+            queue.queued(),
+            QueuedOutput {
+                messages: 1,
+                bytes: 3
+            }
+        );
+    }
+
+    #[test]
+    fn too_big_to_ever_fit() {
+        for policy in [OverflowPolicy::DropOldest, OverflowPolicy::DropNewest] {
+            let queue = queue(None, Some(4), policy);
+
+            assert_eq!(push(&queue, "ab"), Ok(()));
+            assert_eq!(push(&queue, "abcde"), Err((true, false)));
+            assert_eq!(queued(&queue), ["ab"]);
+        }
+    }
+
+    #[test]
+    fn overflows_start_again_once_there_is_room() {
+        let queue = queue(Some(1), None, OverflowPolicy::DropNewest);
+
+        assert_eq!(push(&queue, "a"), Ok(()));
+        assert_eq!(push(&queue, "b"), Err((true, false)));
+
+        // Still overflowing, so it's not reported as a new overflow.
+        assert_eq!(push(&queue, "c"), Err((false, false)));
+
+        queue.state.lock().unwrap().pop();
+
+        assert_eq!(push(&queue, "d"), Ok(()));
+        assert_eq!(push(&queue, "e"), Err((true, false)));
+        assert_eq!(queued(&queue), ["d"]);
+    }
+
+    #[test]
+    fn closed_queues_take_nothing() {
+        let queue = OutboxQueue::new(None);
+
+        assert_eq!(push(&queue, "a"), Ok(()));
+
+        queue.close();
+
+        assert_eq!(push(&queue, "b"), Ok(()));
+        assert_eq!(queued(&queue), ["a"]);
+    }
+}
@@ -1,25 +1,67 @@
-use std::sync::Arc;
+use std::{
+    collections::HashSet,
+    future::Future,
+    io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+    time::Duration,
+};
 
-use bevy::prelude::*;
+use bevy::{log::Level, prelude::*};
+use crossbeam_channel::Sender;
 use dashmap::DashMap;
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::{TcpListener, ToSocketAddrs},
-    runtime::{Builder, Runtime},
-    task::JoinHandle,
+    runtime::{Builder, Handle, Runtime},
+    task::{JoinHandle, JoinSet},
 };
 use uuid::Uuid;
 
 use crate::{
-    channel::Channel,
+    channel::{Capacity, Channel},
     errors::NetworkError,
-    events::{Inbox, IncomingConnection, Message, NetworkEvent, Outbox},
+    events::{DisconnectReason, Inbox, IncomingConnection, Message, NetworkEvent, Received},
+    limits::{FloodPolicy, InputThrottle, RejectReason, Throttle},
+    listener::{BoundListener, ListenerConfig, ListenerId},
+    metrics::{increment, Counters, Metrics},
+    plugin::NestPlugin,
+    proxy,
+    queue::{encode, OutboxQueue},
     telnet::*,
+    timers::{Expiry, Timers},
+    transport::{Acceptor, Transport},
+};
+
+#[cfg(unix)]
+use std::{
+    os::{
+        fd::{AsRawFd, OwnedFd, RawFd},
+        unix::process::CommandExt,
+    },
+    process::Command,
 };
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+#[cfg(feature = "tls")]
+use crate::tls::TlsConfig;
+#[cfg(feature = "websocket")]
+use crate::websocket::WebSocketConfig;
+#[cfg(unix)]
+use crate::{
+    copyover::{self, SavedClient},
+    listener::take_systemd_fds,
+};
+
+// How long a removed client has to take the rest of its output before its connection
+// is closed anyway.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// A unique identifier for a client.
 #[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
-pub struct ClientId(Uuid);
+pub struct ClientId(pub(crate) Uuid);
 
 impl ClientId {
     pub fn new() -> Self {
@@ -33,56 +75,352 @@ impl Default for ClientId {
     }
 }
 
+/// Connection details for a client, available for as long as the client is connected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientInfo {
+    /// The remote address of the client.
+    pub peer_addr: SocketAddr,
+    /// The local address the client connected to.
+    pub local_addr: SocketAddr,
+    /// The listener that accepted the client.
+    pub listener: ListenerId,
+}
+
 struct Client {
-    outbox: Channel<Outbox>,
-    #[allow(dead_code)]
+    info: ClientInfo,
+    // The raw socket, for clients that can be handed over in a copyover.
+    #[cfg(unix)]
+    fd: Option<RawFd>,
+    telnet: Arc<Mutex<TelnetState>>,
+    outbox: Arc<OutboxQueue>,
     read_task: JoinHandle<()>,
-    #[allow(dead_code)]
     write_task: JoinHandle<()>,
 }
 
+impl Drop for Client {
+    fn drop(&mut self) {
+        // The write task finishes what's queued, then closes the connection.
+        self.outbox.close();
+        self.read_task.abort();
+    }
+}
+
+/// How much output is waiting to be written to a client, from
+/// [`Server::queued_output`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueuedOutput {
+    pub messages: usize,
+    pub bytes: usize,
+}
+
+struct Listener {
+    config: Arc<ListenerConfig>,
+    task: JoinHandle<()>,
+    // The socket file of a Unix socket listener, set once it's bound and removed
+    // when the listener is.
+    socket_path: Arc<OnceLock<PathBuf>>,
+    // The socket of a listener started by systemd, handed on in a copyover.
+    #[cfg(unix)]
+    systemd_fd: Option<RawFd>,
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        self.task.abort();
+
+        if let Some(path) = self.socket_path.get() {
+            if let Err(err) = std::fs::remove_file(path) {
+                debug!("Could not remove socket file {}: {err}", path.display());
+            }
+        }
+    }
+}
+
 #[derive(Resource)]
 pub struct Server {
-    runtime: Runtime,
+    // The runtime the server built for itself, if it wasn't given a handle to one.
+    _runtime: Option<Runtime>,
+    runtime: Handle,
     clients: Arc<DashMap<ClientId, Client>>,
+    listeners: DashMap<ListenerId, Listener>,
+    // The clients in each named group.
+    groups: DashMap<String, HashSet<ClientId>>,
+    // The rate limits, shared with the listeners.
+    pub(crate) throttle: Arc<Throttle>,
+    counters: Arc<Counters>,
+    read_buffer_size: usize,
+    // The config for listeners that aren't given one.
+    listener_defaults: Arc<ListenerConfig>,
+    pub(crate) log_level: Level,
     // Incoming connections.
     pub(crate) incoming: Channel<IncomingConnection>,
     // Recently disconnected clients.
-    pub(crate) lost: Channel<ClientId>,
-    // Network events.
-    pub(crate) events: Channel<NetworkEvent>,
-    // Messages received from clients.
-    pub(crate) inbox: Channel<Inbox>,
+    pub(crate) lost: Channel<(ClientId, DisconnectReason)>,
+    // Network events and messages received from clients, in the order they happened.
+    pub(crate) received: Channel<Received>,
+    // Room for messages from clients that haven't been read yet.
+    pub(crate) inbox_capacity: Arc<Capacity>,
+    // Rejected connections still being sent their rejection.
+    rejections: Mutex<JoinSet<()>>,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        // Tasks on a runtime the server was given a handle to would outlive it.
+        self.listeners.clear();
+
+        for client in self.clients.iter() {
+            client.write_task.abort();
+        }
+
+        self.clients.clear();
+        self.rejections.lock().unwrap().abort_all();
+    }
 }
 
 impl Server {
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new(plugin: &NestPlugin) -> Self {
+        let (owned, runtime) = match &plugin.runtime_handle {
+            Some(handle) => (None, handle.clone()),
+            None => {
+                let mut builder = Builder::new_multi_thread();
+
+                if let Some(threads) = plugin.worker_threads {
+                    builder.worker_threads(threads);
+                }
+
+                let runtime = builder
+                    .enable_io()
+                    .enable_time()
+                    .build()
+                    .expect("Could not build runtime");
+                let handle = runtime.handle().clone();
+
+                (Some(runtime), handle)
+            }
+        };
+
         Self {
-            runtime: Builder::new_multi_thread()
-                .enable_io()
-                .build()
-                .expect("Could not build runtime"),
+            _runtime: owned,
+            runtime,
+            read_buffer_size: plugin.read_buffer_size,
+            listener_defaults: Arc::new(plugin.listener_defaults.clone()),
+            log_level: plugin.log_level,
             incoming: Channel::new(),
             clients: Arc::new(DashMap::new()),
+            listeners: DashMap::new(),
+            groups: DashMap::new(),
+            throttle: Arc::new(Throttle::default()),
+            counters: Arc::new(Counters::default()),
             lost: Channel::new(),
-            events: Channel::new(),
-            inbox: Channel::new(),
+            received: Channel::new(),
+            inbox_capacity: Arc::new(Capacity::new(plugin.channel_capacity)),
+            rejections: Mutex::new(JoinSet::new()),
         }
     }
 
-    /// Start listening for incoming connections on the given address.
-    /// This should be called from [`add_startup_system`](bevy::app::App.add_startup_system).
-    pub fn listen(&self, address: impl ToSocketAddrs + Send + 'static) {
-        let events = self.events.sender.clone();
+    /// Start listening for incoming connections on the given address with the default
+    /// [`ListenerConfig`], set with [`NestPlugin::listener_defaults`]. This should be
+    /// called from a [`Startup`] system.
+    pub fn listen(&self, address: impl ToSocketAddrs + Send + 'static) -> ListenerId {
+        self.listen_with(address, (*self.listener_defaults).clone())
+    }
+
+    /// Start listening for incoming connections on the given address. Any number of
+    /// listeners can be running at once, each with its own [`ListenerConfig`].
+    pub fn listen_with(
+        &self,
+        address: impl ToSocketAddrs + Send + 'static,
+        config: ListenerConfig,
+    ) -> ListenerId {
+        self.spawn_listener(tcp_listener(address), config, Acceptor::Plain, None)
+    }
+
+    /// Start listening for TLS connections on the given address with the default
+    /// [`ListenerConfig`]. Once the handshake is done, clients behave exactly like
+    /// plain telnet clients.
+    #[cfg(feature = "tls")]
+    pub fn listen_tls(
+        &self,
+        address: impl ToSocketAddrs + Send + 'static,
+        tls: TlsConfig,
+    ) -> ListenerId {
+        self.listen_tls_with(address, tls, (*self.listener_defaults).clone())
+    }
+
+    /// Start listening for TLS connections on the given address.
+    #[cfg(feature = "tls")]
+    pub fn listen_tls_with(
+        &self,
+        address: impl ToSocketAddrs + Send + 'static,
+        tls: TlsConfig,
+        config: ListenerConfig,
+    ) -> ListenerId {
+        self.spawn_listener(tcp_listener(address), config, Acceptor::tls(tls), None)
+    }
+
+    /// Start listening for WebSocket connections on the given address with the default
+    /// [`ListenerConfig`]. See the [`websocket`](crate::websocket) module for how frames
+    /// map to messages.
+    #[cfg(feature = "websocket")]
+    pub fn listen_websocket(
+        &self,
+        address: impl ToSocketAddrs + Send + 'static,
+        websocket: WebSocketConfig,
+    ) -> ListenerId {
+        self.listen_websocket_with(address, websocket, (*self.listener_defaults).clone())
+    }
+
+    /// Start listening for WebSocket connections on the given address.
+    #[cfg(feature = "websocket")]
+    pub fn listen_websocket_with(
+        &self,
+        address: impl ToSocketAddrs + Send + 'static,
+        websocket: WebSocketConfig,
+        config: ListenerConfig,
+    ) -> ListenerId {
+        // A frame bigger than the client may send at once would only be cut up by the
+        // input limit after it was buffered in full.
+        let max_message_size = config
+            .input_limit
+            .as_ref()
+            .and_then(|limit| limit.bytes)
+            .map_or(self.read_buffer_size, |bytes| {
+                self.read_buffer_size.max(bytes.burst as usize)
+            });
+
+        let acceptor = Acceptor::WebSocket {
+            config: websocket,
+            max_message_size,
+        };
+
+        self.spawn_listener(tcp_listener(address), config, acceptor, None)
+    }
+
+    /// Start listening for connections on a Unix domain socket with the default
+    /// [`ListenerConfig`].
+    #[cfg(unix)]
+    pub fn listen_unix(&self, path: impl AsRef<Path>) -> ListenerId {
+        self.listen_unix_with(path, (*self.listener_defaults).clone())
+    }
+
+    /// Start listening for connections on a Unix domain socket, e.g. for a local
+    /// gateway in front of the game. A stale socket file left at `path` is replaced,
+    /// unless something is still listening on it, and the file is removed again when
+    /// the listener is stopped.
+    ///
+    /// Unix socket clients have no IP address, so their [`ClientInfo`] addresses are
+    /// `0.0.0.0:0`. Set [`ListenerConfig::proxy_protocol`] to have the gateway pass
+    /// along the real ones. Connections to the socket are trusted to send the header
+    /// whatever [`ProxyProtocol::trusted`](crate::listener::ProxyProtocol::trusted)
+    /// says, so limit who can connect with the socket file's permissions.
+    #[cfg(unix)]
+    pub fn listen_unix_with(&self, path: impl AsRef<Path>, config: ListenerConfig) -> ListenerId {
+        let path = path.as_ref().to_path_buf();
+        let bind_path = path.clone();
+
+        let bind = async move {
+            use std::os::unix::fs::FileTypeExt;
+
+            // A socket file left behind by a previous run would make binding fail,
+            // but one that's still being served belongs to someone else.
+            if let Ok(metadata) = std::fs::symlink_metadata(&bind_path) {
+                if metadata.file_type().is_socket() {
+                    match UnixStream::connect(&bind_path).await {
+                        Ok(_) => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::AddrInUse,
+                                "Something is already listening on the socket",
+                            ))
+                        }
+                        Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => {
+                            std::fs::remove_file(&bind_path)?;
+                        }
+                        Err(_) => {}
+                    }
+                }
+            }
+
+            UnixListener::bind(&bind_path).map(BoundListener::Unix)
+        };
+
+        self.spawn_listener(bind, config, Acceptor::Plain, Some(path))
+    }
+
+    /// Start listening on a socket that's already bound, like one inherited from a
+    /// parent process. It can be either a TCP or a Unix domain socket listener.
+    #[cfg(unix)]
+    pub fn listen_from_fd(&self, fd: OwnedFd, config: ListenerConfig) -> ListenerId {
+        let bind = async move { BoundListener::from_fd(fd) };
+
+        self.spawn_listener(bind, config, Acceptor::Plain, None)
+    }
+
+    /// Start listening on the sockets passed to the process by systemd socket
+    /// activation (`LISTEN_FDS`), so systemd can hold the port across restarts and
+    /// bind privileged ports for you. Every socket gets the same config. The sockets
+    /// are kept open across a [`copyover`](Self::copyover), so the new process can
+    /// call this again to pick them back up.
+    ///
+    /// Returns no listeners if the process wasn't socket activated, so it's easy to
+    /// fall back to [`listen`](Self::listen):
+    ///
+    /// ```rust,no_run
+    /// use bevy::prelude::*;
+    /// use bevy_nest::prelude::*;
+    ///
+    /// fn setup_network(server: Res<Server>) {
+    ///     if server.listen_systemd(ListenerConfig::default()).is_empty() {
+    ///         server.listen("0.0.0.0:23");
+    ///     }
+    /// }
+    /// ```
+    #[cfg(unix)]
+    pub fn listen_systemd(&self, config: ListenerConfig) -> Vec<ListenerId> {
+        take_systemd_fds()
+            .into_iter()
+            .map(|fd| {
+                let raw_fd = fd.as_raw_fd();
+                let id = self.listen_from_fd(fd, config.clone());
+
+                if let Some(mut listener) = self.listeners.get_mut(&id) {
+                    listener.systemd_fd = Some(raw_fd);
+                }
+
+                id
+            })
+            .collect()
+    }
+
+    fn spawn_listener(
+        &self,
+        bind: impl Future<Output = io::Result<BoundListener>> + Send + 'static,
+        config: ListenerConfig,
+        acceptor: Acceptor,
+        socket_path: Option<PathBuf>,
+    ) -> ListenerId {
+        let id = config
+            .name
+            .as_deref()
+            .map(ListenerId::named)
+            .unwrap_or_default();
+        let config = Arc::new(config);
+        let events = self.received.sender.clone();
         let incoming = self.incoming.sender.clone();
+        let throttle = self.throttle.clone();
+        let counters = self.counters.clone();
+        let listener_config = config.clone();
+        let log_level = self.log_level;
+        let bound_path = Arc::new(OnceLock::new());
+        let listener_path = bound_path.clone();
 
         // Spawn a new task to listen for incoming connections.
-        self.runtime.spawn(async move {
-            // Create a TCP listener.
-            let listener = match TcpListener::bind(address).await {
+        let task = self.runtime.spawn(async move {
+            let listener = match bind.await {
                 Ok(listener) => listener,
                 Err(err) => {
-                    if let Err(error) = events.send(NetworkEvent::Error(NetworkError::Listen(err)))
+                    if let Err(error) =
+                        events.send(NetworkEvent::Error(NetworkError::Listen(err, id)).into())
                     {
                         error!("Could not send error: {error}");
                     };
@@ -91,23 +429,156 @@ impl Server {
                 }
             };
 
-            info!("Listening");
+            // Only a socket file this listener created is its to remove.
+            if let Some(path) = socket_path {
+                let _ = bound_path.set(path);
+            }
+
+            match listener.local_addr() {
+                Ok(local_addr) => {
+                    log_at!(log_level, "Listening on {local_addr}: {id:?}");
+
+                    if let Err(err) = events.send(
+                        NetworkEvent::Listening {
+                            listener: id,
+                            local_addr,
+                        }
+                        .into(),
+                    ) {
+                        error!("Could not send listening event: {err}");
+                    }
+                }
+                Err(err) => {
+                    if let Err(error) =
+                        events.send(NetworkEvent::Error(NetworkError::Listen(err, id)).into())
+                    {
+                        error!("Could not send error: {error}");
+                    };
+
+                    return;
+                }
+            }
+
+            // Handshakes still going, which stop with the listener.
+            let mut handshakes = JoinSet::new();
 
             loop {
                 // Wait for a new connection.
-                match listener.accept().await {
+                let accepted = listener.accept().await;
+
+                while handshakes.try_join_next().is_some() {}
+
+                match accepted {
                     // If we get a new connection, send it to the incoming channel
                     // to be proccessed later.
-                    Ok((socket, address)) => {
-                        info!("Accepted connection from {address}");
+                    Ok(accepted) => {
+                        let peer_addr = accepted.peer_addr;
+                        let mut stream = accepted.stream;
 
-                        if let Err(err) = incoming.send(IncomingConnection { socket }) {
-                            error!("Failed to send incoming connection: {err}");
+                        log_at!(log_level, "Accepted connection from {peer_addr}");
+
+                        increment(&counters.connections_accepted);
+
+                        // Behind a proxy, the address to throttle is only known once
+                        // the header is read.
+                        if config.proxy_protocol.is_none()
+                            && !admit(&throttle, &counters, &events, peer_addr)
+                        {
+                            continue;
                         }
+
+                        let mut info = ClientInfo {
+                            peer_addr,
+                            local_addr: accepted.local_addr,
+                            listener: id,
+                        };
+
+                        // Only the bare socket can be handed to another process.
+                        #[cfg(unix)]
+                        let fd = matches!(acceptor, Acceptor::Plain).then_some(accepted.fd);
+
+                        let acceptor = acceptor.clone();
+                        let config = config.clone();
+                        let events = events.clone();
+                        let incoming = incoming.clone();
+                        let throttle = throttle.clone();
+                        let counters = counters.clone();
+
+                        // Finish any handshake in its own task so a slow client
+                        // can't hold up the accept loop.
+                        handshakes.spawn(async move {
+                            let deadline = config
+                                .handshake_timeout
+                                .map(|timeout| tokio::time::Instant::now() + timeout);
+
+                            if let Some(proxy_protocol) = &config.proxy_protocol {
+                                let header = if proxy_protocol.trusts(peer_addr) {
+                                    handshake_step(deadline, proxy::read_header(&mut stream)).await
+                                } else {
+                                    Err(io::Error::new(
+                                        io::ErrorKind::PermissionDenied,
+                                        "Connection isn't from a trusted proxy",
+                                    ))
+                                };
+
+                                match header {
+                                    Ok(Some((source, destination))) => {
+                                        info.peer_addr = source;
+                                        info.local_addr = destination;
+                                    }
+                                    Ok(None) => {}
+                                    Err(err) => {
+                                        if let Err(err) = events.send(
+                                            NetworkEvent::Error(NetworkError::Handshake(
+                                                err, peer_addr,
+                                            ))
+                                            .into(),
+                                        ) {
+                                            error!("Could not send error: {err}");
+                                        };
+
+                                        return;
+                                    }
+                                }
+
+                                if !admit(&throttle, &counters, &events, info.peer_addr) {
+                                    return;
+                                }
+                            }
+
+                            let stream =
+                                match handshake_step(deadline, acceptor.accept(stream)).await {
+                                    Ok(stream) => stream,
+                                    Err(err) => {
+                                        if let Err(err) = events.send(
+                                            NetworkEvent::Error(NetworkError::Handshake(
+                                                err, peer_addr,
+                                            ))
+                                            .into(),
+                                        ) {
+                                            error!("Could not send error: {err}");
+                                        };
+
+                                        return;
+                                    }
+                                };
+
+                            if let Err(err) = incoming.send(IncomingConnection {
+                                id: ClientId::new(),
+                                stream,
+                                info,
+                                config,
+                                #[cfg(unix)]
+                                fd,
+                                restored: None,
+                            }) {
+                                error!("Failed to send incoming connection: {err}");
+                            }
+                        });
                     }
                     Err(err) => {
                         if let Err(err) =
-                            events.send(NetworkEvent::Error(NetworkError::Accept(err)))
+                            events.send(NetworkEvent::Error(NetworkError::Accept(err)).into())
                         {
                             error!("Could not send error: {err}");
                         };
@@ -115,69 +586,532 @@ impl Server {
                 }
             }
         });
+
+        self.listeners.insert(
+            id,
+            Listener {
+                config: listener_config,
+                task,
+                socket_path: listener_path,
+                #[cfg(unix)]
+                systemd_fd: None,
+            },
+        );
+
+        id
+    }
+
+    /// Stop accepting connections on a listener. Clients that are already
+    /// connected through it stay connected.
+    pub fn stop_listener(&self, listener_id: &ListenerId) {
+        if self.listeners.remove(listener_id).is_some() {
+            log_at!(self.log_level, "Stopped listening: {listener_id:?}");
+        }
+    }
+
+    // Forget a listener without reporting it as stopped.
+    pub(crate) fn remove_listener(&self, listener_id: &ListenerId) {
+        self.listeners.remove(listener_id);
+    }
+
+    /// Serve a client over a connection that was accepted outside of the server, like
+    /// a Unix socket or an in-memory pipe. It goes through the same pipeline as
+    /// connections accepted by [`listen`](Self::listen). If `info.listener` is one of
+    /// the server's listeners, that listener's [`ListenerConfig`] applies, otherwise
+    /// the [`NestPlugin::listener_defaults`] do.
+    ///
+    /// Returns the id the client will have once it's set up on the next update.
+    ///
+    /// ```rust,no_run
+    /// use bevy::prelude::*;
+    /// use bevy_nest::prelude::*;
+    ///
+    /// fn connect_bot(server: Res<Server>) {
+    ///     let (client, server_side) = tokio::io::duplex(1024);
+    ///     let addr = "127.0.0.1:0".parse().unwrap();
+    ///
+    ///     server.accept_connection(
+    ///         server_side,
+    ///         ClientInfo {
+    ///             peer_addr: addr,
+    ///             local_addr: addr,
+    ///             listener: ListenerId::new(),
+    ///         },
+    ///     );
+    ///
+    ///     // Talk to the server through `client`...
+    /// }
+    /// ```
+    pub fn accept_connection(&self, stream: impl Transport, info: ClientInfo) -> ClientId {
+        let id = ClientId::new();
+        let config = self
+            .listeners
+            .get(&info.listener)
+            .map(|listener| listener.config.clone())
+            .unwrap_or_else(|| self.listener_defaults.clone());
+
+        if let Err(err) = self.incoming.sender.send(IncomingConnection {
+            id,
+            stream: Box::new(stream),
+            info,
+            config,
+            #[cfg(unix)]
+            fd: None,
+            restored: None,
+        }) {
+            error!("Failed to send incoming connection: {err}");
+        }
+
+        id
     }
 
     /// Disconnect a client. This will send a [`NetworkEvent::Disconnected`] event.
     pub fn disconnect(&self, client_id: &ClientId) {
-        self.remove_client(client_id);
+        self.remove_client(client_id, DisconnectReason::Kicked);
+    }
+
+    /// Get the connection details for a client, if it's still connected.
+    pub fn client_info(&self, client_id: &ClientId) -> Option<ClientInfo> {
+        self.clients.get(client_id).map(|client| client.info)
+    }
+
+    /// Add a client to a named group, like a chat channel or a room, so messages can
+    /// be sent to everyone in it with [`Recipient::Group`]. Groups are created when
+    /// their first client joins, and clients leave every group when they disconnect.
+    pub fn join_group(&self, client_id: &ClientId, group: impl Into<String>) {
+        let group = group.into();
+
+        self.groups
+            .entry(group.clone())
+            .or_default()
+            .insert(*client_id);
+
+        // A client removed before it was added would be left in the group, since
+        // it's removed from the clients before it leaves its groups.
+        if !self.clients.contains_key(client_id) {
+            self.leave_group(client_id, &group);
+        }
+    }
+
+    /// Remove a client from a named group.
+    pub fn leave_group(&self, client_id: &ClientId, group: &str) {
+        self.groups.remove_if_mut(group, |_, members| {
+            members.remove(client_id);
+            members.is_empty()
+        });
+    }
+
+    /// Get the clients in a named group.
+    pub fn group_members(&self, group: &str) -> Vec<ClientId> {
+        self.groups
+            .get(group)
+            .map(|members| members.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Get counts of what's happened to connections since the server started.
+    pub fn metrics(&self) -> Metrics {
+        self.counters.snapshot()
+    }
+
+    /// Get how much output is waiting to be written to a client, if it's still
+    /// connected.
+    pub fn queued_output(&self, client_id: &ClientId) -> Option<QueuedOutput> {
+        self.clients
+            .get(client_id)
+            .map(|client| client.outbox.queued())
+    }
+
+    /// Get what a client has replied to telnet negotiation so far, if it's still
+    /// connected.
+    pub fn telnet_state(&self, client_id: &ClientId) -> Option<TelnetState> {
+        self.clients
+            .get(client_id)
+            .map(|client| client.telnet.lock().unwrap().clone())
+    }
+
+    /// Replace this process with a new one without dropping anyone, the classic MUD
+    /// copyover. Every client's socket, [`ClientInfo`] and [`TelnetState`] is written
+    /// to `state_path`, then `command` is exec'd in place of this process. The new
+    /// process picks the clients back up with [`restore_copyover`](Self::restore_copyover).
+    ///
+    /// Only plain TCP and Unix socket clients can be handed over. TLS, WebSocket and
+    /// [`accept_connection`](Self::accept_connection) clients are dropped, as are
+    /// messages that haven't been written yet, so send any "hold on" message a frame
+    /// ahead. Listeners are closed too, except the ones started with
+    /// [`listen_systemd`](Self::listen_systemd), so the new process needs to listen
+    /// again before it restores the clients. Clients come back to the listener with the same
+    /// [`ListenerConfig::name`], so name the listeners to keep their config applied to
+    /// the clients that are handed over.
+    ///
+    /// This only returns if the exec failed, in which case everyone stays connected
+    /// to this process.
+    ///
+    /// ```rust,no_run
+    /// use std::process::Command;
+    ///
+    /// use bevy::prelude::*;
+    /// use bevy_nest::prelude::*;
+    ///
+    /// const COPYOVER_STATE: &str = "copyover.state";
+    ///
+    /// fn setup_network(server: Res<Server>) {
+    ///     server.listen_with(
+    ///         "0.0.0.0:4000",
+    ///         ListenerConfig {
+    ///             name: Some("telnet".into()),
+    ///             ..default()
+    ///         },
+    ///     );
+    ///
+    ///     if let Err(err) = server.restore_copyover(COPYOVER_STATE) {
+    ///         error!("Could not restore copyover: {err}");
+    ///     }
+    /// }
+    ///
+    /// fn copyover(server: Res<Server>) {
+    ///     let program = std::env::current_exe().unwrap();
+    ///     let err = server.copyover(COPYOVER_STATE, Command::new(program));
+    ///
+    ///     error!("Copyover failed: {err}");
+    /// }
+    /// ```
+    #[cfg(unix)]
+    pub fn copyover(&self, state_path: impl AsRef<Path>, mut command: Command) -> io::Error {
+        let state_path = state_path.as_ref();
+
+        let clients = self
+            .clients
+            .iter()
+            .filter_map(|client| {
+                Some(SavedClient {
+                    id: *client.key(),
+                    fd: client.fd?,
+                    info: client.info,
+                    telnet: client.telnet.lock().unwrap().clone(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        if let Err(err) = copyover::save(state_path, &clients) {
+            return err;
+        }
+
+        let listener_fds = self
+            .listeners
+            .iter()
+            .filter_map(|listener| listener.systemd_fd)
+            .collect::<Vec<_>>();
+
+        let inheritable = clients
+            .iter()
+            .map(|client| client.fd)
+            .chain(listener_fds.iter().copied())
+            .try_for_each(|fd| copyover::set_inheritable(fd, true));
+
+        let err = match inheritable {
+            Ok(()) => {
+                info!(
+                    "Copyover: handing {} clients and {} listeners to {command:?}",
+                    clients.len(),
+                    listener_fds.len()
+                );
+
+                command.exec()
+            }
+            Err(err) => err,
+        };
+
+        // Still here, so put everything back the way it was.
+        for client in &clients {
+            if let Err(err) = copyover::set_inheritable(client.fd, false) {
+                debug!("Could not reset socket for {:?}: {err}", client.id);
+            }
+        }
+
+        for fd in listener_fds {
+            if let Err(err) = copyover::set_inheritable(fd, false) {
+                debug!("Could not reset listener socket {fd}: {err}");
+            }
+        }
+
+        if let Err(err) = std::fs::remove_file(state_path) {
+            debug!("Could not remove {}: {err}", state_path.display());
+        }
+
+        err
+    }
+
+    /// Pick up the clients handed over by [`copyover`](Self::copyover). They keep
+    /// their [`ClientId`] and come back with a [`NetworkEvent::Reconnected`] event on
+    /// the next update instead of [`NetworkEvent::Connected`].
+    ///
+    /// Each client gets the config of the listener with the [`ListenerConfig::name`]
+    /// of the one it connected through, so start the listeners first. Clients whose
+    /// listener had no name, or isn't running, get the
+    /// [`NestPlugin::listener_defaults`].
+    ///
+    /// The state file is removed once it's read. If there isn't one, or it was left
+    /// behind by another process, there's nothing to restore, so this is safe to call
+    /// on every startup.
+    #[cfg(unix)]
+    pub fn restore_copyover(&self, state_path: impl AsRef<Path>) -> io::Result<Vec<ClientId>> {
+        let clients = copyover::load(state_path.as_ref())?;
+        let _runtime = self.runtime.enter();
+
+        let mut restored = Vec::new();
+
+        for client in clients {
+            let fd = copyover::take_fd(client.fd);
+
+            // It was only inheritable for the copyover, and shouldn't leak into
+            // processes the game spawns.
+            if let Err(err) = copyover::set_inheritable(client.fd, false) {
+                warn!("Could not set close-on-exec for {:?}: {err}", client.id);
+            }
+
+            let stream = match copyover::stream(fd) {
+                Ok(stream) => stream,
+                Err(err) => {
+                    error!("Could not restore {:?}: {err}", client.id);
+                    continue;
+                }
+            };
+
+            let config = match self.listeners.get(&client.info.listener) {
+                Some(listener) => listener.config.clone(),
+                None => {
+                    warn!(
+                        "No listener for restored {:?}, using the defaults",
+                        client.id
+                    );
+
+                    self.listener_defaults.clone()
+                }
+            };
+
+            if let Err(err) = self.incoming.sender.send(IncomingConnection {
+                id: client.id,
+                stream,
+                info: client.info,
+                config,
+                fd: Some(client.fd),
+                restored: Some(client.telnet),
+            }) {
+                error!("Failed to send incoming connection: {err}");
+                continue;
+            }
+
+            restored.push(client.id);
+        }
+
+        info!("Copyover: restored {} clients", restored.len());
+
+        Ok(restored)
     }
 
     pub(crate) fn setup_client(&self, connection: IncomingConnection) {
-        let (mut read_socket, mut write_socket) = connection.socket.into_split();
+        let info = connection.info;
+        let reconnected = connection.restored.is_some();
 
-        let id = ClientId::new();
-        let outbox: Channel<Outbox> = Channel::new();
+        let (mut read_socket, mut write_socket) = tokio::io::split(connection.stream);
 
-        let read_events_sender = self.events.sender.clone();
-        let write_events_sender = self.events.sender.clone();
-        let inbox_sender = self.inbox.sender.clone();
-        let outbox_receiver = outbox.receiver.clone();
+        let id = connection.id;
+        let outbox = Arc::new(OutboxQueue::new(connection.config.outbox_limit.clone()));
+        let write_outbox = outbox.clone();
+
+        let read_events_sender = self.received.sender.clone();
+        let write_events_sender = self.received.sender.clone();
+        let inbox_sender = self.received.sender.clone();
+        let inbox_capacity = self.inbox_capacity.clone();
         let lost_sender = self.lost.sender.clone();
+        let warning_outbox = outbox.clone();
+        let mut input_throttle = connection
+            .config
+            .input_limit
+            .clone()
+            .map(InputThrottle::new);
+        let read_buffer_size = self.read_buffer_size;
+        let log_level = self.log_level;
+        let mut timers = Timers::new(&connection.config, reconnected);
+        let telnet = Arc::new(Mutex::new(connection.restored.unwrap_or_default()));
+        let read_telnet = telnet.clone();
+
+        // Announce the client before anything can be received from it.
+        let event = if reconnected {
+            NetworkEvent::Reconnected(id, info)
+        } else {
+            NetworkEvent::Connected(id, info)
+        };
+
+        if let Err(err) = self.received.sender.send(event.into()) {
+            error!("Could not send connected event: {err}");
+        }
 
         self.clients.insert(
             id,
             Client {
+                info,
+                #[cfg(unix)]
+                fd: connection.fd,
+                telnet,
                 outbox,
                 // Spawn a new task to read from the socket.
                 // Messages received are sent to the server's inbox.
                 read_task: self.runtime.spawn(async move {
                     // Create a buffer to read data into.
-                    let max_packet_size = 1024;
+                    let max_packet_size = read_buffer_size;
                     let mut buffer = vec![0; max_packet_size];
 
-                    info!("Starting read task for {id:?}");
+                    // Set once the client is on its way out.
+                    let mut disconnecting = false;
+
+                    log_at!(log_level, "Starting read task for {id:?}");
 
                     loop {
-                        // Read data from the socket.
-                        let length = match read_socket.read(&mut buffer).await {
+                        // Read data from the socket, unless a timer runs out first.
+                        let read = read_socket.read(&mut buffer);
+
+                        let result = match timers.next() {
+                            Some((deadline, expiry)) if !disconnecting => {
+                                match tokio::time::timeout_at(deadline.into(), read).await {
+                                    Ok(result) => result,
+                                    Err(_) => {
+                                        match expiry {
+                                            Expiry::Warn => {
+                                                if let Some(warning) = timers.warning() {
+                                                    let warning = Message::Text(warning.into());
+
+                                                    if warning_outbox
+                                                        .push(encode(&warning))
+                                                        .is_err()
+                                                    {
+                                                        debug!("No room to warn idle {id:?}");
+                                                    }
+                                                }
+                                            }
+                                            Expiry::Disconnect(reason) => {
+                                                log_at!(
+                                                    log_level,
+                                                    "Timed out: {id:?} ({reason:?})"
+                                                );
+
+                                                if let Err(err) = lost_sender.send((id, reason)) {
+                                                    error!("Could not send lost connection: {err}");
+                                                }
+
+                                                disconnecting = true;
+                                            }
+                                        }
+
+                                        continue;
+                                    }
+                                }
+                            }
+                            _ => read.await,
+                        };
+
+                        let length = match result {
                             Ok(n) => n,
                             Err(err) => {
-                                if let Err(err) = read_events_sender
-                                    .send(NetworkEvent::Error(NetworkError::SocketRead(err, id)))
-                                {
+                                if let Err(err) = read_events_sender.send(
+                                    NetworkEvent::Error(NetworkError::SocketRead(err, id)).into(),
+                                ) {
                                     error!("Could not send error: {err}");
                                 };
 
+                                if let Err(err) = lost_sender.send((id, DisconnectReason::Error)) {
+                                    error!("Could not send lost connection: {err}");
+                                }
+
                                 break;
                             }
                         };
 
                         // If the length is 0, the socket has been closed.
                         if length == 0 {
-                            if let Err(err) = lost_sender.send(id) {
+                            if let Err(err) = lost_sender.send((id, DisconnectReason::Closed)) {
                                 error!("Could not send lost connection: {err}");
                             }
 
                             break;
                         }
 
+                        // Keep the input flowing until the client is removed, but
+                        // ignore it.
+                        if disconnecting {
+                            continue;
+                        }
+
+                        if let Some(throttle) = &mut input_throttle {
+                            let lines = if buffer[0] == IAC {
+                                0
+                            } else {
+                                buffer[..length]
+                                    .iter()
+                                    .filter(|b| **b == b'\n')
+                                    .count()
+                                    .max(1)
+                            };
+
+                            if let Err(flood) = throttle.check(length, lines) {
+                                if flood.started {
+                                    log_at!(log_level, "Client is flooding: {id:?}");
+
+                                    if let Err(err) =
+                                        read_events_sender.send(NetworkEvent::InputFlood(id).into())
+                                    {
+                                        error!("Could not send event: {err}");
+                                    }
+                                }
+
+                                match throttle.policy() {
+                                    FloodPolicy::Delay => tokio::time::sleep(flood.delay).await,
+                                    FloodPolicy::Drop(warning) => {
+                                        if flood.started {
+                                            let warning = Message::Text(warning.clone());
+
+                                            if warning_outbox.push(encode(&warning)).is_err() {
+                                                debug!("No room to warn {id:?} about flooding");
+                                            }
+                                        }
+
+                                        continue;
+                                    }
+                                    FloodPolicy::Disconnect => {
+                                        if let Err(err) =
+                                            lost_sender.send((id, DisconnectReason::InputFlood))
+                                        {
+                                            error!("Could not send lost connection: {err}");
+                                        }
+
+                                        disconnecting = true;
+
+                                        continue;
+                                    }
+                                }
+                            }
+                        }
+
+                        {
+                            let mut telnet = read_telnet.lock().unwrap();
+
+                            telnet.record(&buffer[..length]);
+                            timers.input(&telnet);
+                        }
+
                         if buffer[0] == 255 {
                             // This is a command because the first byte is 255.
                             // See: https://users.cs.cf.ac.uk/Dave.Marshall/Internet/node141.html
-                            if let Err(error) = inbox_sender.send(Inbox {
-                                from: id,
-                                content: Message::Command(buffer[..length].to_vec()),
-                            }) {
+                            inbox_capacity.reserve().await;
+
+                            if let Err(error) = inbox_sender.send(
+                                Inbox {
+                                    from: id,
+                                    content: Message::Command(buffer[..length].to_vec()),
+                                }
+                                .into(),
+                            ) {
                                 error!("Could not send to inbox: {error}");
                             }
                         } else {
@@ -186,10 +1120,16 @@ impl Server {
 
                             // Send the message to the inbox.
                             if !clean.is_empty() {
-                                if let Err(error) = inbox_sender.send(Inbox {
-                                    from: id,
-                                    content: Message::Text(clean.into()),
-                                }) {
+                                timers.line();
+                                inbox_capacity.reserve().await;
+
+                                if let Err(error) = inbox_sender.send(
+                                    Inbox {
+                                        from: id,
+                                        content: Message::Text(clean.into()),
+                                    }
+                                    .into(),
+                                ) {
                                     error!("Could not send to inbox: {error}");
                                 }
                             }
@@ -197,116 +1137,526 @@ impl Server {
                     }
                 }),
                 write_task: self.runtime.spawn(async move {
-                    // Iterate over messages received from the outbox
-                    // and write them to the socket.
-                    while let Ok(out) = outbox_receiver.recv() {
-                        match out.content {
-                            Message::Text(text) => {
-                                if let Err(err) =
-                                    write_socket.write_all((text + "\r\n").as_bytes()).await
-                                {
-                                    if let Err(err) = write_events_sender.send(NetworkEvent::Error(
-                                        NetworkError::SocketWrite(err, out.to),
-                                    )) {
-                                        error!("Could not send error: {err}");
-                                    };
-
-                                    break;
-                                }
-                            }
-                            Message::Command(command) => {
-                                if let Err(err) = write_socket.write_all(command.as_slice()).await {
-                                    if let Err(err) = write_events_sender.send(NetworkEvent::Error(
-                                        NetworkError::SocketWrite(err, out.to),
-                                    )) {
-                                        error!("Could not send error: {err}");
-                                    };
-
-                                    break;
-                                }
-                            }
-                            Message::GMCP(payload) => {
-                                let mut seq = vec![IAC, SB, GMCP];
+                    // Write messages from the outbox to the socket as they're queued.
+                    while let Some(bytes) = write_outbox.pop().await {
+                        // Flush after every message so transports that frame their
+                        // output, like WebSockets, send one frame per message.
+                        let result = match write_socket.write_all(&bytes).await {
+                            Ok(()) => write_socket.flush().await,
+                            Err(err) => Err(err),
+                        };
 
-                                seq.extend(payload.package.as_bytes());
+                        if let Err(err) = result {
+                            if let Err(err) = write_events_sender.send(
+                                NetworkEvent::Error(NetworkError::SocketWrite(err, id)).into(),
+                            ) {
+                                error!("Could not send error: {err}");
+                            };
 
-                                if let Some(subpackage) = payload.subpackage {
-                                    seq.push(b'.');
-                                    seq.extend(subpackage.as_bytes());
-                                }
+                            break;
+                        }
+                    }
 
-                                if let Some(data) = payload.data {
-                                    seq.push(b' ');
-                                    seq.extend(data.as_bytes());
-                                }
+                    // The client was removed, so close the connection once everything
+                    // queued has been written.
+                    if let Err(err) = write_socket.shutdown().await {
+                        debug!("Could not shut down socket for {id:?}: {err}");
+                    }
+                }),
+            },
+        );
 
-                                seq.extend(vec![IAC, SE]);
+        if reconnected {
+            return;
+        }
 
-                                if let Err(err) = write_socket.write_all(seq.as_slice()).await {
-                                    if let Err(err) = write_events_sender.send(NetworkEvent::Error(
-                                        NetworkError::SocketWrite(err, out.to),
-                                    )) {
-                                        error!("Could not send error: {err}");
-                                    };
+        if let Some(client) = self.clients.get(&id) {
+            for (verb, option) in &connection.config.negotiate {
+                let command = Message::Command(vec![IAC, *verb, *option]);
 
-                                    break;
-                                }
-                            }
-                        }
-                    }
-                }),
+                self.enqueue(&id, &client, encode(&command));
+            }
+
+            if let Some(greeting) = &connection.config.greeting {
+                self.enqueue(&id, &client, encode(&Message::Text(greeting.clone())));
+            }
+        }
+    }
+
+    // The connection details of every connected client.
+    pub(crate) fn connected(&self) -> Vec<ClientInfo> {
+        self.clients.iter().map(|client| client.info).collect()
+    }
+
+    // Close a connection that broke the limits without setting it up as a client.
+    pub(crate) fn reject(
+        &self,
+        connection: IncomingConnection,
+        reason: RejectReason,
+        message: Option<String>,
+    ) {
+        let addr = connection.info.peer_addr;
+        let mut stream = connection.stream;
+
+        log_at!(
+            self.log_level,
+            "Rejected connection from {addr}: {reason:?}"
+        );
+
+        increment(&self.counters.connections_rejected);
+
+        let reject = async move {
+            if let Some(message) = message {
+                let bytes = (message + "\r\n").into_bytes();
+
+                let result = match stream.write_all(&bytes).await {
+                    Ok(()) => stream.flush().await,
+                    Err(err) => Err(err),
+                };
+
+                if let Err(err) = result {
+                    debug!("Could not send rejection to {addr}: {err}");
+                }
+            }
+
+            if let Err(err) = stream.shutdown().await {
+                debug!("Could not shut down socket for {addr}: {err}");
+            }
+        };
+
+        let mut rejections = self.rejections.lock().unwrap();
+
+        while rejections.try_join_next().is_some() {}
+
+        // A connection that won't take the message can't hold its socket open.
+        rejections.spawn_on(
+            async move {
+                if tokio::time::timeout(DRAIN_TIMEOUT, reject).await.is_err() {
+                    debug!("Gave up sending rejection to {addr}");
+                }
             },
+            &self.runtime,
         );
 
-        if let Err(err) = self.events.sender.send(NetworkEvent::Connected(id)) {
-            error!("Could not send connected event: {err}");
+        drop(rejections);
+
+        if let Err(err) = self
+            .received
+            .sender
+            .send(NetworkEvent::ConnectionRejected { addr, reason }.into())
+        {
+            error!("Could not send event: {err}");
         }
     }
 
     // Remove a client from the server.
-    pub(crate) fn remove_client(&self, id: &ClientId) {
-        self.clients.remove(id);
+    pub(crate) fn remove_client(&self, id: &ClientId, reason: DisconnectReason) {
+        let Some((_, client)) = self.clients.remove(id) else {
+            return;
+        };
+
+        // Dropping the client stops reading from it and closes its outbox, which ends
+        // the write task once everything queued is written, unless the client stops
+        // reading. A slow consumer already has, so there's no point waiting for it.
+        if reason == DisconnectReason::SlowConsumer {
+            client.write_task.abort();
+        } else {
+            let write_task = client.write_task.abort_handle();
+
+            self.runtime.spawn(async move {
+                tokio::time::sleep(DRAIN_TIMEOUT).await;
+                write_task.abort();
+            });
+        }
+
+        self.groups.retain(|_, members| {
+            members.remove(id);
+            !members.is_empty()
+        });
 
-        info!("Client disconnected: {id:?}");
+        log_at!(self.log_level, "Client disconnected: {id:?} ({reason:?})");
 
-        if let Err(err) = self.events.sender.send(NetworkEvent::Disconnected(*id)) {
+        if let Err(err) = self
+            .received
+            .sender
+            .send(NetworkEvent::Disconnected(*id, reason).into())
+        {
             error!("Could not send event: {err}");
         }
     }
 
-    /// Send a message to a client's outbox.
-    pub(crate) fn send(&self, out: &Outbox) {
-        match &out.content {
-            Message::Text(text) => {
-                if let Some(client) = self.clients.get(&out.to) {
-                    if let Err(err) = client.outbox.sender.send(Outbox {
-                        to: out.to,
-                        content: Message::Text(text.clone()),
-                    }) {
-                        error!("Could not send message: {err}");
-                    }
-                }
+    // Send an encoded message to a client's outbox.
+    pub(crate) fn send(&self, id: &ClientId, bytes: Arc<[u8]>) {
+        if let Some(client) = self.clients.get(id) {
+            self.enqueue(id, &client, bytes);
+        }
+    }
+
+    // Send an encoded message to every connected client.
+    pub(crate) fn send_to_all(&self, bytes: Arc<[u8]>) {
+        for client in self.clients.iter() {
+            self.enqueue(client.key(), &client, bytes.clone());
+        }
+    }
+
+    // Send an encoded message to everyone in a group.
+    pub(crate) fn send_to_group(&self, group: &str, bytes: Arc<[u8]>) {
+        for id in self.group_members(group) {
+            self.send(&id, bytes.clone());
+        }
+    }
+
+    // Queue a message for a client, reporting it if the outbox overflows.
+    fn enqueue(&self, id: &ClientId, client: &Client, bytes: Arc<[u8]>) {
+        let Err(overflow) = client.outbox.push(bytes) else {
+            return;
+        };
+
+        if !overflow.started {
+            return;
+        }
+
+        log_at!(self.log_level, "Outbox overflowed: {id:?}");
+
+        if let Err(err) = self
+            .received
+            .sender
+            .send(NetworkEvent::OutboxOverflow(*id).into())
+        {
+            error!("Could not send event: {err}");
+        }
+
+        if overflow.disconnect {
+            if let Err(err) = self.lost.sender.send((*id, DisconnectReason::SlowConsumer)) {
+                error!("Could not send lost connection: {err}");
             }
-            Message::Command(command) => {
-                if let Some(client) = self.clients.get(&out.to) {
-                    if let Err(err) = client.outbox.sender.send(Outbox {
-                        to: out.to,
-                        content: Message::Command(command.clone()),
-                    }) {
-                        error!("Could not send message: {err}");
-                    }
-                }
+        }
+    }
+}
+
+// Run a step of a new connection's handshake, giving up at the deadline.
+async fn handshake_step<T>(
+    deadline: Option<tokio::time::Instant>,
+    step: impl Future<Output = io::Result<T>>,
+) -> io::Result<T> {
+    let Some(deadline) = deadline else {
+        return step.await;
+    };
+
+    tokio::time::timeout_at(deadline, step)
+        .await
+        .unwrap_or_else(|_| {
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Handshake timed out",
+            ))
+        })
+}
+
+// Check a new connection against the rate limits, reporting it if it's throttled.
+fn admit(
+    throttle: &Throttle,
+    counters: &Counters,
+    events: &Sender<Received>,
+    addr: SocketAddr,
+) -> bool {
+    let Err(reason) = throttle.check(addr.ip()) else {
+        return true;
+    };
+
+    debug!("Throttled connection from {addr}");
+
+    increment(&counters.connections_throttled);
+
+    if let Err(err) = events.send(NetworkEvent::ConnectionRejected { addr, reason }.into()) {
+        error!("Could not send event: {err}");
+    }
+
+    false
+}
+
+// Bind a TCP listener to the given address.
+async fn tcp_listener(address: impl ToSocketAddrs) -> io::Result<BoundListener> {
+    TcpListener::bind(address).await.map(BoundListener::Tcp)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        time::{Duration, Instant},
+    };
+
+    use bevy::prelude::*;
+
+    use crate::prelude::*;
+
+    #[derive(Resource, Default)]
+    struct Connected(Vec<ClientInfo>);
+
+    fn watch(mut connected: ResMut<Connected>, mut events: EventReader<NetworkEvent>) {
+        for event in events.read() {
+            if let NetworkEvent::Connected(_, info) = event {
+                connected.0.push(*info);
             }
-            Message::GMCP(payload) => {
-                if let Some(client) = self.clients.get(&out.to) {
-                    if let Err(err) = client.outbox.sender.send(Outbox {
-                        to: out.to,
-                        content: Message::GMCP(payload.clone()),
-                    }) {
-                        error!("Could not send message: {err}");
-                    }
+        }
+    }
+
+    #[test]
+    fn accepts_on_a_listener_from_an_fd() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut app = App::new();
+
+        app.add_plugins(NestPlugin::default())
+            .init_resource::<Connected>()
+            .add_systems(Update, watch);
+
+        let listener_id = app
+            .world()
+            .resource::<Server>()
+            .listen_from_fd(listener.into(), ListenerConfig::default());
+
+        let _client = std::net::TcpStream::connect(addr).unwrap();
+        let started = Instant::now();
+
+        while app.world().resource::<Connected>().0.is_empty() {
+            assert!(started.elapsed() < Duration::from_secs(5), "Timed out");
+
+            std::thread::sleep(Duration::from_millis(10));
+            app.update();
+        }
+
+        let info = app.world().resource::<Connected>().0[0];
+
+        assert_eq!(info.listener, listener_id);
+        assert_eq!(info.local_addr, addr);
+    }
+
+    #[test]
+    fn trusts_proxy_headers_on_unix_sockets() {
+        let path = std::env::temp_dir().join(format!("bevy-nest-{}.sock", uuid::Uuid::new_v4()));
+
+        let mut app = App::new();
+
+        app.add_plugins(NestPlugin::default())
+            .init_resource::<Connected>()
+            .add_systems(Update, watch);
+
+        app.world().resource::<Server>().listen_unix_with(
+            &path,
+            ListenerConfig {
+                proxy_protocol: Some(ProxyProtocol { trusted: vec![] }),
+                ..default()
+            },
+        );
+
+        let started = Instant::now();
+
+        let mut client = loop {
+            if let Ok(client) = std::os::unix::net::UnixStream::connect(&path) {
+                break client;
+            }
+
+            assert!(started.elapsed() < Duration::from_secs(5), "Timed out");
+
+            std::thread::sleep(Duration::from_millis(10));
+        };
+
+        client
+            .write_all(b"PROXY TCP4 203.0.113.7 10.0.0.1 5555 23\r\n")
+            .unwrap();
+
+        while app.world().resource::<Connected>().0.is_empty() {
+            assert!(started.elapsed() < Duration::from_secs(5), "Timed out");
+
+            std::thread::sleep(Duration::from_millis(10));
+            app.update();
+        }
+
+        let info = app.world().resource::<Connected>().0[0];
+
+        assert_eq!(info.peer_addr, "203.0.113.7:5555".parse().unwrap());
+        assert_eq!(info.local_addr, "10.0.0.1:23".parse().unwrap());
+    }
+
+    #[test]
+    fn stops_its_tasks_when_dropped() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut app = App::new();
+
+        app.add_plugins(NestPlugin::default().runtime_handle(runtime.handle().clone()))
+            .init_resource::<Connected>()
+            .add_systems(Update, watch);
+
+        app.world()
+            .resource::<Server>()
+            .listen_from_fd(listener.into(), ListenerConfig::default());
+
+        let mut client = std::net::TcpStream::connect(addr).unwrap();
+        let started = Instant::now();
+
+        while app.world().resource::<Connected>().0.is_empty() {
+            assert!(started.elapsed() < Duration::from_secs(5), "Timed out");
+
+            std::thread::sleep(Duration::from_millis(10));
+            app.update();
+        }
+
+        // The runtime lives on, but the server's tasks shouldn't.
+        drop(app);
+
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        assert_eq!(client.read(&mut [0; 64]).unwrap(), 0);
+
+        let started = Instant::now();
+
+        while std::net::TcpStream::connect(addr).is_ok() {
+            assert!(started.elapsed() < Duration::from_secs(5), "Timed out");
+
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn stops_handshakes_when_dropped() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut app = App::new();
+
+        app.add_plugins(NestPlugin::default().runtime_handle(runtime.handle().clone()));
+
+        app.world().resource::<Server>().listen_from_fd(
+            listener.into(),
+            ListenerConfig {
+                proxy_protocol: Some(ProxyProtocol {
+                    trusted: vec!["127.0.0.0/8".parse().unwrap()],
+                }),
+                handshake_timeout: None,
+                ..default()
+            },
+        );
+
+        // Never sends its PROXY header, so it never gets past the handshake.
+        let mut client = std::net::TcpStream::connect(addr).unwrap();
+
+        std::thread::sleep(Duration::from_millis(100));
+        app.update();
+
+        drop(app);
+
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        assert_eq!(client.read(&mut [0; 64]).unwrap(), 0);
+    }
+
+    #[derive(Resource, Default)]
+    struct Restored {
+        reconnected: Option<(ClientId, ClientInfo)>,
+        disconnected: Option<(ClientId, DisconnectReason)>,
+    }
+
+    fn watch_restored(mut restored: ResMut<Restored>, mut events: EventReader<NetworkEvent>) {
+        for event in events.read() {
+            match event {
+                NetworkEvent::Reconnected(id, info) => restored.reconnected = Some((*id, *info)),
+                NetworkEvent::Disconnected(id, reason) => {
+                    restored.disconnected = Some((*id, *reason));
                 }
+                _ => {}
             }
         }
     }
+
+    #[test]
+    fn restored_clients_get_their_listeners_config() {
+        use std::os::fd::IntoRawFd;
+
+        use crate::{
+            copyover::{self, SavedClient},
+            telnet::TelnetState,
+        };
+
+        let state_path =
+            std::env::temp_dir().join(format!("bevy-nest-{}.state", uuid::Uuid::new_v4()));
+
+        // A connection accepted by the process before the copyover.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (accepted, peer_addr) = listener.accept().unwrap();
+        let id = ClientId::new();
+        let info = ClientInfo {
+            peer_addr,
+            local_addr: accepted.local_addr().unwrap(),
+            listener: ListenerId::named("telnet"),
+        };
+
+        copyover::save(
+            &state_path,
+            &[SavedClient {
+                id,
+                fd: accepted.into_raw_fd(),
+                info,
+                telnet: TelnetState::default(),
+            }],
+        )
+        .unwrap();
+
+        // The new process starts the same listener, with a config only it has.
+        let mut app = App::new();
+
+        app.add_plugins(NestPlugin::default())
+            .init_resource::<Restored>()
+            .add_systems(Update, watch_restored);
+
+        let server = app.world().resource::<Server>();
+
+        server.listen_with(
+            "127.0.0.1:0",
+            ListenerConfig {
+                name: Some("telnet".into()),
+                idle_timeout: Some(IdleTimeout {
+                    timeout: Duration::from_millis(200),
+                    warning: None,
+                }),
+                ..default()
+            },
+        );
+
+        assert_eq!(server.restore_copyover(&state_path).unwrap(), [id]);
+
+        let started = Instant::now();
+
+        while app.world().resource::<Restored>().disconnected.is_none() {
+            assert!(started.elapsed() < Duration::from_secs(5), "Timed out");
+
+            std::thread::sleep(Duration::from_millis(10));
+            app.update();
+        }
+
+        let restored = app.world().resource::<Restored>();
+
+        assert_eq!(restored.reconnected, Some((id, info)));
+        assert_eq!(
+            restored.disconnected,
+            Some((id, DisconnectReason::IdleTimeout))
+        );
+
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        assert_eq!(client.read(&mut [0; 64]).unwrap(), 0);
+    }
 }
@@ -0,0 +1,513 @@
+//! An optional session layer on top of [`ClientId`]s, for keeping a player in the
+//! world while their connection drops and comes back.
+//!
+//! With the [`SessionPlugin`] added, every client gets a session when it connects.
+//! When the client disconnects, its session goes link-dead for a grace period
+//! instead of ending. A new connection can take the session back over, either by
+//! presenting the session's resume token or once the game has authenticated it, and
+//! the game sees [`SessionEvent`]s instead of raw connects and disconnects.
+//!
+//! ```rust
+//! use bevy::prelude::*;
+//! use bevy_nest::{prelude::*, session::*, testing::MockClient};
+//!
+//! fn resume(mut inbox: EventReader<Inbox>, mut sessions: ResMut<Sessions>) {
+//!     for message in inbox.read() {
+//!         if let Message::Text(text) = &message.content {
+//!             if let Some(token) = text.strip_prefix("resume ") {
+//!                 sessions.resume_with_token(message.from, token);
+//!             }
+//!         }
+//!     }
+//! }
+//!
+//! let mut app = App::new();
+//!
+//! app.add_plugins((NestPlugin::default(), SessionPlugin::default()))
+//!     .add_systems(Update, resume);
+//!
+//! let client = MockClient::connect(&mut app);
+//! app.update();
+//!
+//! let sessions = app.world().resource::<Sessions>();
+//! let session = sessions.session(&client.id()).unwrap();
+//! let token = sessions.token(&session).unwrap().to_string();
+//!
+//! // The connection drops, but the session lives on.
+//! drop(client);
+//!
+//! let started = std::time::Instant::now();
+//!
+//! while !app.world().resource::<Sessions>().is_link_dead(&session) {
+//!     assert!(started.elapsed() < std::time::Duration::from_secs(5), "Timed out");
+//!
+//!     std::thread::sleep(std::time::Duration::from_millis(10));
+//!     app.update();
+//! }
+//!
+//! let mut client = MockClient::connect(&mut app);
+//!
+//! client.send_line(&format!("resume {token}"));
+//! app.update();
+//! app.update();
+//!
+//! assert_eq!(app.world().resource::<Sessions>().session(&client.id()), Some(session));
+//! ```
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::*;
+use uuid::Uuid;
+
+use crate::{
+    events::NetworkEvent,
+    plugin::{NestSchedules, NestSet},
+    server::{ClientId, Server},
+};
+
+/// A unique identifier for a session, which outlives the connections attached to it.
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
+pub struct SessionId(Uuid);
+
+impl SessionId {
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+}
+
+impl Default for SessionId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Event, Clone, PartialEq, Eq)]
+pub enum SessionEvent {
+    /// A client connected and a new session was started for it.
+    Started(SessionId, ClientId),
+    /// The session's client disconnected. It's link-dead until it's resumed or the
+    /// grace period runs out.
+    Suspended(SessionId),
+    /// A new client took the session over. If the session's previous client was
+    /// still connected, it's disconnected.
+    Resumed(SessionId, ClientId),
+    /// The session was ended, either by the game or because it stayed link-dead for
+    /// longer than the grace period.
+    Ended(SessionId),
+}
+
+/// Adds the [`Sessions`] resource and [`SessionEvent`]s. Requires the
+/// [`NestPlugin`](crate::plugin::NestPlugin), which has to be added first.
+pub struct SessionPlugin {
+    /// How long a session stays link-dead before it's ended.
+    pub grace_period: Duration,
+}
+
+impl Default for SessionPlugin {
+    fn default() -> Self {
+        Self {
+            grace_period: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+impl Plugin for SessionPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Sessions::new(self.grace_period));
+
+        app.add_event::<SessionEvent>();
+
+        let schedule = app
+            .world()
+            .get_resource::<NestSchedules>()
+            .expect("The NestPlugin has to be added before the SessionPlugin")
+            .receive;
+
+        app.add_systems(schedule, update_sessions.after(NestSet::Receive));
+    }
+}
+
+struct Session {
+    client: Option<ClientId>,
+    token: String,
+    // When the session went link-dead.
+    suspended_at: Option<Instant>,
+}
+
+/// Every live and link-dead session.
+#[derive(Resource)]
+pub struct Sessions {
+    grace_period: Duration,
+    sessions: HashMap<SessionId, Session>,
+    clients: HashMap<ClientId, SessionId>,
+    tokens: HashMap<String, SessionId>,
+    // Events and disconnects waiting for the next update.
+    pending_events: Vec<SessionEvent>,
+    pending_disconnects: Vec<ClientId>,
+}
+
+impl Sessions {
+    fn new(grace_period: Duration) -> Self {
+        Self {
+            grace_period,
+            sessions: HashMap::new(),
+            clients: HashMap::new(),
+            tokens: HashMap::new(),
+            pending_events: Vec::new(),
+            pending_disconnects: Vec::new(),
+        }
+    }
+
+    /// The session a client is attached to.
+    pub fn session(&self, client_id: &ClientId) -> Option<SessionId> {
+        self.clients.get(client_id).copied()
+    }
+
+    /// The client attached to a session, if it isn't link-dead.
+    pub fn client(&self, session_id: &SessionId) -> Option<ClientId> {
+        self.sessions.get(session_id)?.client
+    }
+
+    /// The token a new connection can present to take the session over. It changes
+    /// every time the session is resumed, so hand the new one to the client then.
+    pub fn token(&self, session_id: &SessionId) -> Option<&str> {
+        self.sessions
+            .get(session_id)
+            .map(|session| session.token.as_str())
+    }
+
+    /// Whether a session is waiting for its client to come back.
+    pub fn is_link_dead(&self, session_id: &SessionId) -> bool {
+        self.sessions
+            .get(session_id)
+            .is_some_and(|session| session.client.is_none())
+    }
+
+    /// Attach a client to the session with the given resume token. The session the
+    /// client started with is ended. Returns `None` if no session has the token.
+    pub fn resume_with_token(&mut self, client_id: ClientId, token: &str) -> Option<SessionId> {
+        let session_id = self.tokens.get(token).copied()?;
+
+        self.resume(client_id, session_id).then_some(session_id)
+    }
+
+    /// Attach a client to a session, e.g. once the game has authenticated it as the
+    /// session's player. The session the client started with is ended. Returns `false`
+    /// if the session doesn't exist.
+    pub fn resume(&mut self, client_id: ClientId, session_id: SessionId) -> bool {
+        if !self.sessions.contains_key(&session_id) {
+            return false;
+        }
+
+        if self.clients.get(&client_id) == Some(&session_id) {
+            return true;
+        }
+
+        // The session the client started with isn't needed any more.
+        if let Some(previous) = self.clients.remove(&client_id) {
+            self.remove(&previous);
+            self.pending_events.push(SessionEvent::Ended(previous));
+        }
+
+        let token = new_token();
+        let session = self.sessions.get_mut(&session_id).unwrap();
+
+        // A dropped connection often isn't noticed until the player is back.
+        if let Some(replaced) = session.client.replace(client_id) {
+            self.clients.remove(&replaced);
+            self.pending_disconnects.push(replaced);
+        }
+
+        session.suspended_at = None;
+
+        self.tokens.remove(&session.token);
+        self.tokens.insert(token.clone(), session_id);
+        session.token = token;
+
+        self.clients.insert(client_id, session_id);
+        self.pending_events
+            .push(SessionEvent::Resumed(session_id, client_id));
+
+        true
+    }
+
+    /// End a session, disconnecting its client if it has one.
+    pub fn end(&mut self, session_id: &SessionId) {
+        if let Some(session) = self.remove(session_id) {
+            if let Some(client_id) = session.client {
+                self.clients.remove(&client_id);
+                self.pending_disconnects.push(client_id);
+            }
+
+            self.pending_events.push(SessionEvent::Ended(*session_id));
+        }
+    }
+
+    fn start(&mut self, client_id: ClientId) -> SessionId {
+        let session_id = SessionId::new();
+        let token = new_token();
+
+        self.tokens.insert(token.clone(), session_id);
+        self.clients.insert(client_id, session_id);
+        self.sessions.insert(
+            session_id,
+            Session {
+                client: Some(client_id),
+                token,
+                suspended_at: None,
+            },
+        );
+
+        session_id
+    }
+
+    fn suspend(&mut self, client_id: &ClientId) -> Option<SessionId> {
+        let session_id = self.clients.remove(client_id)?;
+        let session = self.sessions.get_mut(&session_id)?;
+
+        session.client = None;
+        session.suspended_at = Some(Instant::now());
+
+        Some(session_id)
+    }
+
+    fn remove(&mut self, session_id: &SessionId) -> Option<Session> {
+        let session = self.sessions.remove(session_id)?;
+
+        self.tokens.remove(&session.token);
+
+        Some(session)
+    }
+
+    // Link-dead sessions that have run out of time.
+    fn expired(&self) -> Vec<SessionId> {
+        self.sessions
+            .iter()
+            .filter(|(_, session)| {
+                session
+                    .suspended_at
+                    .is_some_and(|at| at.elapsed() >= self.grace_period)
+            })
+            .map(|(id, _)| *id)
+            .collect()
+    }
+}
+
+fn new_token() -> String {
+    Uuid::new_v4().simple().to_string()
+}
+
+// Keep sessions in step with connects and disconnects, and end the ones that have
+// been link-dead for too long.
+fn update_sessions(
+    server: Res<Server>,
+    mut sessions: ResMut<Sessions>,
+    mut network_events: EventReader<NetworkEvent>,
+    mut session_events: EventWriter<SessionEvent>,
+) {
+    for event in network_events.read() {
+        match event {
+            NetworkEvent::Connected(client_id, _) | NetworkEvent::Reconnected(client_id, _) => {
+                let session_id = sessions.start(*client_id);
+
+                log_at!(
+                    server.log_level,
+                    "Session started: {session_id:?} for {client_id:?}"
+                );
+
+                sessions
+                    .pending_events
+                    .push(SessionEvent::Started(session_id, *client_id));
+            }
+            NetworkEvent::Disconnected(client_id, _) => {
+                if let Some(session_id) = sessions.suspend(client_id) {
+                    log_at!(server.log_level, "Session link-dead: {session_id:?}");
+
+                    sessions
+                        .pending_events
+                        .push(SessionEvent::Suspended(session_id));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for session_id in sessions.expired() {
+        log_at!(server.log_level, "Session expired: {session_id:?}");
+
+        sessions.end(&session_id);
+    }
+
+    for client_id in std::mem::take(&mut sessions.pending_disconnects) {
+        server.disconnect(&client_id);
+    }
+
+    for event in &sessions.pending_events {
+        if let SessionEvent::Resumed(session_id, client_id) = event {
+            log_at!(
+                server.log_level,
+                "Session resumed: {session_id:?} by {client_id:?}"
+            );
+        }
+    }
+
+    session_events.send_batch(std::mem::take(&mut sessions.pending_events));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use bevy::prelude::*;
+
+    use super::*;
+    use crate::{prelude::*, testing::MockClient};
+
+    #[derive(Resource, Default)]
+    struct Seen(Vec<SessionEvent>);
+
+    fn watch(mut seen: ResMut<Seen>, mut events: EventReader<SessionEvent>) {
+        seen.0.extend(events.read().cloned());
+    }
+
+    fn app(grace_period: Duration) -> App {
+        let mut app = App::new();
+
+        app.add_plugins((NestPlugin::default(), SessionPlugin { grace_period }))
+            .init_resource::<Seen>()
+            .add_systems(Update, watch);
+
+        app
+    }
+
+    fn update_until(app: &mut App, mut done: impl FnMut(&App) -> bool) {
+        let started = Instant::now();
+
+        while !done(app) {
+            assert!(started.elapsed() < Duration::from_secs(5), "Timed out");
+
+            std::thread::sleep(Duration::from_millis(10));
+            app.update();
+        }
+    }
+
+    fn sessions(app: &mut App) -> Mut<'_, Sessions> {
+        app.world_mut().resource_mut::<Sessions>()
+    }
+
+    fn seen(app: &mut App) -> Vec<SessionEvent> {
+        std::mem::take(&mut app.world_mut().resource_mut::<Seen>().0)
+    }
+
+    #[test]
+    fn link_dead_sessions_end_after_the_grace_period() {
+        let mut app = app(Duration::from_millis(50));
+        let client = MockClient::connect(&mut app);
+
+        app.update();
+
+        let session = sessions(&mut app).session(&client.id()).unwrap();
+
+        drop(client);
+
+        update_until(&mut app, |app| {
+            app.world()
+                .resource::<Seen>()
+                .0
+                .contains(&SessionEvent::Ended(session))
+        });
+
+        assert_eq!(
+            seen(&mut app)[1..],
+            [
+                SessionEvent::Suspended(session),
+                SessionEvent::Ended(session)
+            ]
+        );
+        assert_eq!(sessions(&mut app).token(&session), None);
+    }
+
+    #[test]
+    fn resuming_replaces_a_connected_client() {
+        let mut app = app(Duration::from_secs(60));
+        let mut old = MockClient::connect(&mut app);
+        let new = MockClient::connect(&mut app);
+
+        app.update();
+
+        let session = sessions(&mut app).session(&old.id()).unwrap();
+        let started_with = sessions(&mut app).session(&new.id()).unwrap();
+
+        seen(&mut app);
+
+        assert!(sessions(&mut app).resume(new.id(), session));
+
+        app.update();
+
+        assert_eq!(
+            seen(&mut app),
+            [
+                SessionEvent::Ended(started_with),
+                SessionEvent::Resumed(session, new.id())
+            ]
+        );
+        assert_eq!(sessions(&mut app).client(&session), Some(new.id()));
+        assert_eq!(sessions(&mut app).session(&old.id()), None);
+
+        old.expect_closed();
+
+        // The replaced client's disconnect doesn't suspend the session.
+        update_until(&mut app, |app| {
+            app.world()
+                .resource::<Server>()
+                .client_info(&old.id())
+                .is_none()
+        });
+        app.update();
+
+        assert!(!sessions(&mut app).is_link_dead(&session));
+    }
+
+    #[test]
+    fn resuming_rotates_the_token() {
+        let mut app = app(Duration::from_secs(60));
+        let first = MockClient::connect(&mut app);
+
+        app.update();
+
+        let session = sessions(&mut app).session(&first.id()).unwrap();
+        let token = sessions(&mut app).token(&session).unwrap().to_string();
+
+        drop(first);
+
+        update_until(&mut app, |app| {
+            app.world().resource::<Sessions>().is_link_dead(&session)
+        });
+
+        let second = MockClient::connect(&mut app);
+
+        assert_eq!(
+            sessions(&mut app).resume_with_token(second.id(), &token),
+            Some(session)
+        );
+
+        let rotated = sessions(&mut app).token(&session).unwrap().to_string();
+
+        assert_ne!(rotated, token);
+
+        // The old token can't be used again.
+        let third = MockClient::connect(&mut app);
+
+        assert_eq!(
+            sessions(&mut app).resume_with_token(third.id(), &token),
+            None
+        );
+        assert_eq!(
+            sessions(&mut app).resume_with_token(third.id(), &rotated),
+            Some(session)
+        );
+    }
+}
@@ -1,13 +1,37 @@
+use std::collections::HashSet;
+
 use crate::{
-    events::{Inbox, NetworkEvent, Outbox},
-    server::Server,
+    connection::Connection,
+    errors::NetworkError,
+    events::{Inbox, NetworkEvent, Outbox, Received, Recipient},
+    limits::ConnectionLimits,
+    queue::encode,
+    server::{ClientId, Server},
 };
 use bevy::prelude::*;
 
 // Retrieve incoming connections from the server and spawn tasks to handle them.
-pub(crate) fn handle_incoming(server: Res<Server>) {
+pub(crate) fn handle_incoming(server: Res<Server>, limits: Res<ConnectionLimits>) {
+    if limits.is_changed() {
+        server.throttle.configure(limits.rate);
+    }
+
     for connection in server.incoming.receiver.try_iter() {
-        info!("Handling incoming connection: {connection:?}");
+        log_at!(
+            server.log_level,
+            "Handling incoming connection: {connection:?}"
+        );
+
+        // Clients restored from a copyover were already let in.
+        if connection.restored.is_none() {
+            let connected = server.connected();
+            let max_clients = connection.config.max_clients;
+
+            if let Err(reason) = limits.check(&connection.info, max_clients, &connected) {
+                server.reject(connection, reason, limits.message_for(reason));
+                continue;
+            }
+        }
 
         server.setup_client(connection);
     }
@@ -15,34 +39,258 @@ pub(crate) fn handle_incoming(server: Res<Server>) {
 
 // Retrieve lost clients from the server and remove them from the client list.
 pub(crate) fn handle_lost(server: Res<Server>) {
-    for id in server.lost.receiver.try_iter() {
-        info!("Handling lost connection: {id:?}");
+    for (id, reason) in server.lost.receiver.try_iter() {
+        log_at!(server.log_level, "Handling lost connection: {id:?}");
 
-        server.remove_client(&id);
+        server.remove_client(&id, reason);
     }
 }
 
-// Retrieve events from the server and send them to Bevy.
-pub(crate) fn handle_events(server: Res<Server>, mut events: EventWriter<NetworkEvent>) {
-    for event in server.events.receiver.try_iter() {
-        info!("Handling event: {event:?}");
+// Keeps each client's events and messages in order from one update to the next.
+#[derive(Default)]
+pub(crate) struct Ordering {
+    // Received from clients that connected this update, held back for the next one.
+    deferred: Vec<Received>,
+    connected: HashSet<ClientId>,
+    // Clients that disconnected this update or the last, whose stray messages are
+    // dropped.
+    gone: HashSet<ClientId>,
+    was_gone: HashSet<ClientId>,
+}
+
+// Retrieve events and messages from the server and send them to Bevy. A client's
+// messages are never seen before its connected event, or after its disconnected one.
+pub(crate) fn handle_received(
+    server: Res<Server>,
+    mut ordering: Local<Ordering>,
+    mut events: EventWriter<NetworkEvent>,
+    mut inbox: EventWriter<Inbox>,
+) {
+    let deferred = std::mem::take(&mut ordering.deferred);
+
+    ordering.connected.clear();
+    ordering.was_gone = std::mem::take(&mut ordering.gone);
+
+    let received: Vec<Received> = server.received.receiver.try_iter().collect();
+
+    // Once they're out of the channel, clients can send more.
+    server.inbox_capacity.release(
+        received
+            .iter()
+            .filter(|received| matches!(received, Received::Inbox(_)))
+            .count(),
+    );
+
+    for received in deferred.into_iter().chain(received) {
+        let client = match &received {
+            Received::Event(event) => client_of(event),
+            Received::Inbox(message) => Some(message.from),
+        };
+
+        if let Some(id) = client {
+            // Held back so the game can see the client connect first.
+            if ordering.connected.contains(&id) {
+                ordering.deferred.push(received);
+                continue;
+            }
+        }
+
+        match received {
+            Received::Event(event) => {
+                log_at!(server.log_level, "Handling event: {event:?}");
 
-        events.send(event);
+                match &event {
+                    NetworkEvent::Connected(id, _) | NetworkEvent::Reconnected(id, _) => {
+                        ordering.connected.insert(*id);
+                    }
+                    NetworkEvent::Disconnected(id, _) => {
+                        ordering.gone.insert(*id);
+                    }
+                    // A listener that couldn't bind isn't listening.
+                    NetworkEvent::Error(NetworkError::Listen(_, listener)) => {
+                        server.remove_listener(listener);
+                    }
+                    _ => {}
+                }
+
+                events.send(event);
+            }
+            Received::Inbox(message) => {
+                if ordering.gone.contains(&message.from)
+                    || ordering.was_gone.contains(&message.from)
+                {
+                    debug!("Dropping message from disconnected {:?}", message.from);
+                    continue;
+                }
+
+                log_at!(server.log_level, "Handling inbox message: {message:?}");
+
+                inbox.send(message);
+            }
+        }
     }
 }
 
-// Retrieve messages from the server and send them to Bevy.
-pub(crate) fn handle_inbox(server: Res<Server>, mut inbox: EventWriter<Inbox>) {
-    for message in server.inbox.receiver.try_iter() {
-        info!("Handling inbox message: {message:?}");
-
-        inbox.send(message);
+// The client an event is about, if any.
+fn client_of(event: &NetworkEvent) -> Option<ClientId> {
+    match event {
+        NetworkEvent::Connected(id, _)
+        | NetworkEvent::Reconnected(id, _)
+        | NetworkEvent::Disconnected(id, _)
+        | NetworkEvent::InputFlood(id)
+        | NetworkEvent::OutboxOverflow(id) => Some(*id),
+        _ => None,
     }
 }
 
 // Retrieve messages from Bevy and send them to the server.
-pub(crate) fn handle_outbox(server: Res<Server>, mut outbox: EventReader<Outbox>) {
+pub(crate) fn handle_outbox(
+    server: Res<Server>,
+    mut outbox: EventReader<Outbox>,
+    connections: Query<&Connection>,
+) {
     for out in outbox.read() {
-        server.send(out);
+        // Encoded once, however many clients it's for.
+        let bytes = encode(&out.content);
+
+        match &out.to {
+            Recipient::Client(id) => server.send(id, bytes),
+            Recipient::Entity(entity) => match connections.get(*entity) {
+                Ok(connection) => server.send(&connection.id, bytes),
+                Err(_) => debug!("Dropping message to {entity}, which has no connection"),
+            },
+            Recipient::Many(ids) => {
+                for id in ids {
+                    server.send(id, bytes.clone());
+                }
+            }
+            Recipient::Group(group) => server.send_to_group(group, bytes),
+            Recipient::All => server.send_to_all(bytes),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use crate::{
+        events::Received,
+        listener::ListenerId,
+        prelude::*,
+        server::{ClientId, ClientInfo},
+    };
+
+    #[derive(Resource, Default)]
+    struct Seen(Vec<String>);
+
+    fn watch(
+        mut seen: ResMut<Seen>,
+        mut events: EventReader<NetworkEvent>,
+        mut inbox: EventReader<Inbox>,
+    ) {
+        // Each update's events, then its messages.
+        for event in events.read() {
+            match event {
+                NetworkEvent::Connected(..) => seen.0.push("connected".into()),
+                NetworkEvent::Disconnected(..) => seen.0.push("disconnected".into()),
+                _ => {}
+            }
+        }
+
+        for message in inbox.read() {
+            if let Message::Text(text) = &message.content {
+                seen.0.push(text.clone());
+            }
+        }
+    }
+
+    fn app() -> App {
+        let mut app = App::new();
+
+        app.add_plugins(NestPlugin::default())
+            .init_resource::<Seen>()
+            .add_systems(Update, watch);
+
+        app
+    }
+
+    // Queue things up as if they'd all come from the network since the last update.
+    fn receive(app: &App, received: impl IntoIterator<Item = Received>) {
+        for received in received {
+            app.world()
+                .resource::<Server>()
+                .received
+                .sender
+                .send(received)
+                .unwrap();
+        }
+    }
+
+    // Run an update and return what the game saw in it.
+    fn update(app: &mut App) -> Vec<String> {
+        app.update();
+
+        std::mem::take(&mut app.world_mut().resource_mut::<Seen>().0)
+    }
+
+    fn connected(id: ClientId) -> Received {
+        let addr = "127.0.0.1:4000".parse().unwrap();
+
+        NetworkEvent::Connected(
+            id,
+            ClientInfo {
+                peer_addr: addr,
+                local_addr: addr,
+                listener: ListenerId::new(),
+            },
+        )
+        .into()
+    }
+
+    fn disconnected(id: ClientId) -> Received {
+        NetworkEvent::Disconnected(id, DisconnectReason::Closed).into()
+    }
+
+    fn line(id: ClientId, text: &str) -> Received {
+        Inbox {
+            from: id,
+            content: Message::Text(text.into()),
+        }
+        .into()
+    }
+
+    #[test]
+    fn messages_wait_for_the_update_after_connected() {
+        let mut app = app();
+        let (id, other) = (ClientId::new(), ClientId::new());
+
+        receive(&app, [connected(id), line(id, "look"), line(id, "north")]);
+        receive(&app, [connected(other), disconnected(other)]);
+
+        assert_eq!(update(&mut app), ["connected", "connected"]);
+        assert_eq!(update(&mut app), ["disconnected", "look", "north"]);
+        assert!(update(&mut app).is_empty());
+    }
+
+    #[test]
+    fn nothing_is_delivered_after_disconnected() {
+        let mut app = app();
+        let id = ClientId::new();
+
+        receive(&app, [connected(id)]);
+        update(&mut app);
+
+        receive(
+            &app,
+            [line(id, "quit"), disconnected(id), line(id, "stray")],
+        );
+
+        assert_eq!(update(&mut app), ["disconnected", "quit"]);
+
+        // The read task can still be finishing up an update later.
+        receive(&app, [line(id, "late")]);
+
+        assert!(update(&mut app).is_empty());
     }
 }
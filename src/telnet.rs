@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+
 /// Interpret as command
 pub const IAC: u8 = 255;
 /// Begin option subnegotiation
@@ -16,3 +18,48 @@ pub const DONT: u8 = 254;
 pub const GMCP: u8 = 201;
 /// Echo
 pub const ECHO: u8 = 1;
+
+/// What a client has replied to each telnet option negotiated with it, available
+/// through [`Server::telnet_state`](crate::server::Server::telnet_state).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TelnetState {
+    // The last verb the client sent for each option.
+    pub(crate) replies: BTreeMap<u8, u8>,
+}
+
+impl TelnetState {
+    /// The last [`WILL`], [`WONT`], [`DO`] or [`DONT`] the client sent for an option.
+    pub fn reply(&self, option: u8) -> Option<u8> {
+        self.replies.get(&option).copied()
+    }
+
+    /// Whether the client agreed to an option with [`WILL`] or [`DO`].
+    pub fn is_enabled(&self, option: u8) -> bool {
+        matches!(self.reply(option), Some(WILL) | Some(DO))
+    }
+
+    // Record every negotiation in the bytes read from a client.
+    pub(crate) fn record(&mut self, bytes: &[u8]) {
+        let mut i = 0;
+
+        while i + 2 < bytes.len() {
+            if bytes[i] == IAC {
+                match bytes[i + 1] {
+                    WILL | WONT | DO | DONT => {
+                        self.replies.insert(bytes[i + 2], bytes[i + 1]);
+                        i += 3;
+                        continue;
+                    }
+                    // An escaped 255 in the data.
+                    IAC => {
+                        i += 2;
+                        continue;
+                    }
+                    _ => {}
+                }
+            }
+
+            i += 1;
+        }
+    }
+}
@@ -0,0 +1,352 @@
+//! An in-memory harness for testing games built on bevy-nest without opening sockets.
+//!
+//! [`MockClient`]s are connected to the app's [`Server`] through in-memory pipes and
+//! go through the same pipeline as real clients, so what they receive is exactly what
+//! a telnet client would see, negotiation and GMCP included.
+//!
+//! ```rust
+//! use bevy::prelude::*;
+//! use bevy_nest::{prelude::*, testing::MockClient};
+//!
+//! fn ping_pong(mut inbox: EventReader<Inbox>, mut outbox: EventWriter<Outbox>) {
+//!     for message in inbox.read() {
+//!         if message.content == Message::Text("ping".into()) {
+//!             outbox.send_text(message.from, "pong!");
+//!         }
+//!     }
+//! }
+//!
+//! let mut app = App::new();
+//!
+//! app.add_plugins(NestPlugin::default()).add_systems(Update, ping_pong);
+//!
+//! let mut client = MockClient::connect(&mut app);
+//!
+//! client.send_line("ping");
+//! app.update();
+//!
+//! client.expect_line("pong!");
+//! ```
+
+use std::{
+    collections::VecDeque,
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::*;
+use tokio::{
+    io::{duplex, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream, ReadBuf},
+    runtime::{Builder, Runtime},
+};
+
+use crate::{
+    events::{Message, Payload},
+    listener::ListenerId,
+    server::{ClientId, ClientInfo, Server},
+    telnet::*,
+};
+
+// How long to wait for the server before failing a test.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A client connected to the app's [`Server`] through an in-memory pipe.
+pub struct MockClient {
+    id: ClientId,
+    runtime: Runtime,
+    stream: DuplexStream,
+    // Bytes written to the server so far.
+    written: usize,
+    // Bytes the server has read and finished handling so far.
+    handled: Arc<AtomicUsize>,
+    // Bytes received that don't make up a whole message yet.
+    buffer: Vec<u8>,
+    received: VecDeque<Message>,
+}
+
+impl MockClient {
+    /// Connect a new client to the app's [`Server`]. This runs one update so the
+    /// client is set up by the time it returns.
+    pub fn connect(app: &mut App) -> Self {
+        let addr = "127.0.0.1:0".parse().unwrap();
+
+        Self::connect_with(
+            app,
+            ClientInfo {
+                peer_addr: addr,
+                local_addr: addr,
+                listener: ListenerId::new(),
+            },
+        )
+    }
+
+    /// Connect a new client with the given connection details, e.g. to act as if it
+    /// came in through a particular listener or from a particular address.
+    pub fn connect_with(app: &mut App, info: ClientInfo) -> Self {
+        let (stream, server_stream) = duplex(64 * 1024);
+        let handled = Arc::new(AtomicUsize::new(0));
+
+        let id = app.world().resource::<Server>().accept_connection(
+            MockTransport {
+                stream: server_stream,
+                handled: handled.clone(),
+                pending: 0,
+            },
+            info,
+        );
+
+        app.update();
+
+        Self {
+            id,
+            runtime: Builder::new_current_thread()
+                .enable_time()
+                .build()
+                .expect("Could not build runtime"),
+            stream,
+            written: 0,
+            handled,
+            buffer: Vec::new(),
+            received: VecDeque::new(),
+        }
+    }
+
+    /// The id the server knows this client by.
+    pub fn id(&self) -> ClientId {
+        self.id
+    }
+
+    /// Send raw bytes to the server. This waits until the server has handled them,
+    /// so they show up in the [`Inbox`](crate::events::Inbox) on the next update.
+    pub fn send_bytes(&mut self, bytes: &[u8]) {
+        self.runtime
+            .block_on(self.stream.write_all(bytes))
+            .expect("Could not write to server");
+
+        self.written += bytes.len();
+
+        let started = Instant::now();
+
+        while self.handled.load(Ordering::SeqCst) < self.written {
+            assert!(
+                started.elapsed() < TIMEOUT,
+                "Timed out waiting for the server to read from {:?}",
+                self.id
+            );
+
+            std::thread::yield_now();
+        }
+    }
+
+    /// Send a line of text to the server, like a player pressing enter.
+    pub fn send_line(&mut self, line: &str) {
+        self.send_bytes(format!("{line}\r\n").as_bytes());
+    }
+
+    /// Wait for the next message from the server. Returns `None` if nothing arrives
+    /// before the timeout or the server closed the connection.
+    pub fn recv(&mut self) -> Option<Message> {
+        self.recv_timeout(TIMEOUT)
+    }
+
+    /// Wait up to `timeout` for the next message from the server.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Option<Message> {
+        let deadline = Instant::now() + timeout;
+
+        while self.received.is_empty() {
+            let mut chunk = [0; 1024];
+            let remaining = deadline.saturating_duration_since(Instant::now());
+
+            let read = self.runtime.block_on(async {
+                tokio::time::timeout(remaining, self.stream.read(&mut chunk)).await
+            });
+
+            match read {
+                Ok(Ok(length)) if length > 0 => {
+                    self.buffer.extend_from_slice(&chunk[..length]);
+                    self.decode();
+                }
+                _ => break,
+            }
+        }
+
+        self.received.pop_front()
+    }
+
+    /// Every message that has arrived so far, without waiting for more.
+    pub fn received(&mut self) -> Vec<Message> {
+        let mut messages = Vec::new();
+
+        while let Some(message) = self.recv_timeout(Duration::ZERO) {
+            messages.push(message);
+        }
+
+        messages
+    }
+
+    /// Assert the next message is the given line of text.
+    #[track_caller]
+    pub fn expect_line(&mut self, line: &str) {
+        match self.recv() {
+            Some(Message::Text(text)) if text == line => {}
+            other => panic!("Expected line {line:?}, got {other:?}"),
+        }
+    }
+
+    /// Assert the next message is the given telnet command, e.g. `[IAC, WILL, GMCP]`.
+    #[track_caller]
+    pub fn expect_command(&mut self, command: &[u8]) {
+        match self.recv() {
+            Some(Message::Command(bytes)) if bytes == command => {}
+            other => panic!("Expected command {command:?}, got {other:?}"),
+        }
+    }
+
+    /// Assert the next message is the given GMCP payload.
+    #[track_caller]
+    pub fn expect_gmcp(&mut self, payload: &Payload) {
+        match self.recv() {
+            Some(Message::GMCP(received)) if gmcp_name(&received) == gmcp_name(payload) => {
+                assert_eq!(received.data, payload.data, "GMCP data didn't match");
+            }
+            other => panic!("Expected GMCP {payload:?}, got {other:?}"),
+        }
+    }
+
+    /// Assert the server closed the connection.
+    #[track_caller]
+    pub fn expect_closed(&mut self) {
+        if let Some(message) = self.recv() {
+            panic!("Expected the connection to be closed, got {message:?}");
+        }
+    }
+
+    // Split the received bytes into lines, commands and GMCP messages.
+    fn decode(&mut self) {
+        loop {
+            let buffer = &self.buffer;
+
+            let (message, length) = if buffer.first() == Some(&IAC) {
+                match buffer.get(1) {
+                    None => return,
+                    Some(&SB) => {
+                        let Some(end) = buffer.windows(2).position(|w| w == [IAC, SE]) else {
+                            return;
+                        };
+
+                        let command = &buffer[..end + 2];
+
+                        if command.get(2) == Some(&GMCP) {
+                            (Message::GMCP(parse_gmcp(&command[3..end])), end + 2)
+                        } else {
+                            (Message::Command(command.to_vec()), end + 2)
+                        }
+                    }
+                    Some(&WILL) | Some(&WONT) | Some(&DO) | Some(&DONT) => {
+                        if buffer.len() < 3 {
+                            return;
+                        }
+
+                        (Message::Command(buffer[..3].to_vec()), 3)
+                    }
+                    Some(_) => (Message::Command(buffer[..2].to_vec()), 2),
+                }
+            } else {
+                let Some(end) = buffer.iter().position(|byte| *byte == b'\n') else {
+                    return;
+                };
+
+                let line = String::from_utf8_lossy(&buffer[..end]);
+
+                (Message::Text(line.trim_end_matches('\r').into()), end + 1)
+            };
+
+            self.received.push_back(message);
+            self.buffer.drain(..length);
+        }
+    }
+}
+
+// The full GMCP name, e.g. `Char.Vitals`.
+fn gmcp_name(payload: &Payload) -> String {
+    match &payload.subpackage {
+        Some(subpackage) => format!("{}.{subpackage}", payload.package),
+        None => payload.package.clone(),
+    }
+}
+
+fn parse_gmcp(bytes: &[u8]) -> Payload {
+    let text = String::from_utf8_lossy(bytes);
+
+    let (name, data) = match text.split_once(' ') {
+        Some((name, data)) => (name, Some(data.to_string())),
+        None => (text.as_ref(), None),
+    };
+
+    let (package, subpackage) = match name.split_once('.') {
+        Some((package, subpackage)) => (package.to_string(), Some(subpackage.to_string())),
+        None => (name.to_string(), None),
+    };
+
+    Payload {
+        package,
+        subpackage,
+        data,
+    }
+}
+
+// The server's end of the pipe. It keeps count of how much input the server has
+// handled so a mock client can wait for it.
+struct MockTransport {
+    stream: DuplexStream,
+    handled: Arc<AtomicUsize>,
+    // Bytes returned by the last read. The read task handles everything from one read
+    // before it reads again, so these are handled once the next read starts.
+    pending: usize,
+}
+
+impl AsyncRead for MockTransport {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        this.handled.fetch_add(this.pending, Ordering::SeqCst);
+        this.pending = 0;
+
+        let filled = buf.filled().len();
+        let result = Pin::new(&mut this.stream).poll_read(cx, buf);
+
+        if let Poll::Ready(Ok(())) = result {
+            this.pending = buf.filled().len() - filled;
+        }
+
+        result
+    }
+}
+
+impl AsyncWrite for MockTransport {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
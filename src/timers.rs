@@ -0,0 +1,92 @@
+use std::time::Instant;
+
+use crate::{
+    events::DisconnectReason,
+    listener::{IdleTimeout, ListenerConfig},
+    telnet::TelnetState,
+};
+
+// What's due when a client's next timer runs out.
+pub(crate) enum Expiry {
+    Warn,
+    Disconnect(DisconnectReason),
+}
+
+// Keeps track of how long a client has been idle, or stuck negotiating.
+pub(crate) struct Timers {
+    idle: Option<IdleTimeout>,
+    negotiation: Option<std::time::Duration>,
+    // The options the client has to answer for negotiation to be done.
+    options: Vec<u8>,
+    connected_at: Instant,
+    last_line_at: Instant,
+    warned: bool,
+    negotiated: bool,
+}
+
+impl Timers {
+    pub(crate) fn new(config: &ListenerConfig, negotiated: bool) -> Self {
+        let now = Instant::now();
+
+        Self {
+            idle: config.idle_timeout.clone(),
+            negotiation: config.negotiation_timeout,
+            options: config.negotiate.iter().map(|(_, option)| *option).collect(),
+            connected_at: now,
+            last_line_at: now,
+            warned: false,
+            negotiated,
+        }
+    }
+
+    // The next timer to run out, if any are running.
+    pub(crate) fn next(&self) -> Option<(Instant, Expiry)> {
+        if !self.negotiated {
+            if let Some(timeout) = self.negotiation {
+                return Some((
+                    self.connected_at + timeout,
+                    Expiry::Disconnect(DisconnectReason::NegotiationTimeout),
+                ));
+            }
+        }
+
+        let idle = self.idle.as_ref()?;
+        let kick_at = self.last_line_at + idle.timeout;
+
+        match &idle.warning {
+            Some((before, _)) if !self.warned => Some((
+                kick_at.checked_sub(*before).unwrap_or(kick_at),
+                Expiry::Warn,
+            )),
+            _ => Some((kick_at, Expiry::Disconnect(DisconnectReason::IdleTimeout))),
+        }
+    }
+
+    // The text to warn an idle client with.
+    pub(crate) fn warning(&mut self) -> Option<&str> {
+        self.warned = true;
+
+        self.idle
+            .as_ref()?
+            .warning
+            .as_ref()
+            .map(|(_, message)| message.as_str())
+    }
+
+    // The client sent something, which may have been its last negotiation reply.
+    pub(crate) fn input(&mut self, telnet: &TelnetState) {
+        if !self.negotiated {
+            self.negotiated = self
+                .options
+                .iter()
+                .all(|option| telnet.reply(*option).is_some());
+        }
+    }
+
+    // The client sent a line of text, so it's done negotiating and no longer idle.
+    pub(crate) fn line(&mut self) {
+        self.negotiated = true;
+        self.last_line_at = Instant::now();
+        self.warned = false;
+    }
+}
@@ -0,0 +1,193 @@
+//! TLS support for listeners, enabled with the `tls` feature.
+
+use std::{io, path::Path, sync::Arc};
+
+use tokio_rustls::rustls::{
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    ServerConfig,
+};
+
+pub use tokio_rustls::rustls;
+
+/// Certificates and keys for a TLS listener, passed to
+/// [`Server::listen_tls`](crate::server::Server::listen_tls).
+///
+/// ```rust,no_run
+/// use bevy::prelude::*;
+/// use bevy_nest::{prelude::*, tls::TlsConfig};
+///
+/// fn setup_network(server: Res<Server>) {
+///     let tls = TlsConfig::from_pem_files("cert.pem", "key.pem").expect("Could not load TLS config");
+///
+///     server.listen("0.0.0.0:4000");
+///     server.listen_tls("0.0.0.0:4443", tls);
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub(crate) server_config: Arc<ServerConfig>,
+}
+
+impl TlsConfig {
+    /// Load a certificate chain and private key from PEM files.
+    pub fn from_pem_files(
+        cert_path: impl AsRef<Path>,
+        key_path: impl AsRef<Path>,
+    ) -> io::Result<Self> {
+        let certs = CertificateDer::pem_file_iter(cert_path)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        let key = PrivateKeyDer::from_pem_file(key_path)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        let server_config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        Ok(Self::from(server_config))
+    }
+}
+
+impl From<ServerConfig> for TlsConfig {
+    /// Use an already built rustls [`ServerConfig`].
+    fn from(server_config: ServerConfig) -> Self {
+        Self::from(Arc::new(server_config))
+    }
+}
+
+impl From<Arc<ServerConfig>> for TlsConfig {
+    /// Use an already built rustls [`ServerConfig`].
+    fn from(server_config: Arc<ServerConfig>) -> Self {
+        Self { server_config }
+    }
+}
+
+#[cfg(all(test, feature = "tls"))]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use bevy::prelude::*;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio_rustls::{
+        rustls::{
+            pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName},
+            ClientConfig, RootCertStore, ServerConfig,
+        },
+        TlsConnector,
+    };
+
+    use crate::prelude::*;
+
+    #[derive(Resource, Default)]
+    struct Seen {
+        addr: Option<SocketAddr>,
+        connected: bool,
+        lines: Vec<String>,
+    }
+
+    fn watch(
+        mut seen: ResMut<Seen>,
+        mut events: EventReader<NetworkEvent>,
+        mut inbox: EventReader<Inbox>,
+    ) {
+        for event in events.read() {
+            match event {
+                NetworkEvent::Listening { local_addr, .. } => seen.addr = Some(*local_addr),
+                NetworkEvent::Connected(..) => seen.connected = true,
+                _ => {}
+            }
+        }
+
+        for message in inbox.read() {
+            if let Message::Text(text) = &message.content {
+                seen.lines.push(text.clone());
+            }
+        }
+    }
+
+    fn update_until<T>(app: &mut App, mut done: impl FnMut(&Seen) -> Option<T>) -> T {
+        let started = Instant::now();
+
+        loop {
+            app.update();
+
+            if let Some(value) = done(app.world().resource::<Seen>()) {
+                return value;
+            }
+
+            assert!(started.elapsed() < Duration::from_secs(5), "Timed out");
+
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn round_trip_with_a_self_signed_cert() {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let cert_der = CertificateDer::from(cert.cert.der().to_vec());
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der()));
+
+        let server_config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert_der.clone()], key)
+            .unwrap();
+
+        let mut app = App::new();
+
+        app.add_plugins(NestPlugin::default())
+            .init_resource::<Seen>()
+            .add_systems(Update, watch);
+
+        app.world().resource::<Server>().listen_tls_with(
+            "127.0.0.1:0",
+            server_config.into(),
+            ListenerConfig {
+                greeting: Some("Welcome!".into()),
+                ..default()
+            },
+        );
+
+        let addr = update_until(&mut app, |seen| seen.addr);
+
+        let mut roots = RootCertStore::empty();
+        roots.add(cert_der).unwrap();
+
+        let connector = TlsConnector::from(Arc::new(
+            ClientConfig::builder()
+                .with_root_certificates(roots)
+                .with_no_client_auth(),
+        ));
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let client = runtime.spawn(async move {
+            let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let name = ServerName::try_from("localhost").unwrap();
+            let stream = connector.connect(name, stream).await.unwrap();
+            let (read, mut write) = tokio::io::split(stream);
+
+            let mut greeting = String::new();
+            BufReader::new(read).read_line(&mut greeting).await.unwrap();
+
+            write.write_all(b"hello\r\n").await.unwrap();
+            write.flush().await.unwrap();
+
+            // Stay connected until the server has read the line.
+            tokio::time::sleep(Duration::from_secs(1)).await;
+
+            greeting
+        });
+
+        let line = update_until(&mut app, |seen| seen.lines.first().cloned());
+
+        assert!(app.world().resource::<Seen>().connected);
+        assert_eq!(line, "hello");
+        assert_eq!(runtime.block_on(client).unwrap(), "Welcome!\r\n");
+    }
+}
@@ -0,0 +1,58 @@
+//! The byte streams clients are served over.
+
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+#[cfg(feature = "tls")]
+use crate::tls::TlsConfig;
+#[cfg(feature = "websocket")]
+use crate::websocket::{WebSocketAdapter, WebSocketConfig};
+
+/// Any bidirectional byte stream a client can be served over. This is implemented
+/// for everything that's [`AsyncRead`] + [`AsyncWrite`], so TCP and Unix streams, TLS
+/// streams or in-memory pipes like [`tokio::io::duplex`] can all be handed to
+/// [`Server::accept_connection`](crate::server::Server::accept_connection).
+pub trait Transport: AsyncRead + AsyncWrite + Send + Unpin + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Transport for T {}
+
+// How a listener turns an accepted stream into the transport clients are served over.
+#[derive(Clone)]
+pub(crate) enum Acceptor {
+    Plain,
+    #[cfg(feature = "tls")]
+    Tls(tokio_rustls::TlsAcceptor),
+    #[cfg(feature = "websocket")]
+    WebSocket {
+        config: WebSocketConfig,
+        // The biggest frame or message a client can send.
+        max_message_size: usize,
+    },
+}
+
+impl Acceptor {
+    #[cfg(feature = "tls")]
+    pub(crate) fn tls(config: TlsConfig) -> Self {
+        Acceptor::Tls(tokio_rustls::TlsAcceptor::from(config.server_config))
+    }
+
+    // Perform any handshake the listener needs before the client can be set up.
+    pub(crate) async fn accept(
+        &self,
+        stream: Box<dyn Transport>,
+    ) -> io::Result<Box<dyn Transport>> {
+        match self {
+            Acceptor::Plain => Ok(stream),
+            #[cfg(feature = "tls")]
+            Acceptor::Tls(acceptor) => Ok(Box::new(acceptor.accept(stream).await?)),
+            #[cfg(feature = "websocket")]
+            Acceptor::WebSocket {
+                config,
+                max_message_size,
+            } => Ok(Box::new(
+                WebSocketAdapter::accept(stream, *config, *max_message_size).await?,
+            )),
+        }
+    }
+}
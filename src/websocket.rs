@@ -0,0 +1,445 @@
+//! WebSocket support for listeners, enabled with the `websocket` feature.
+//!
+//! Browser clients connect like any other client and get a regular [`ClientId`](crate::server::ClientId).
+//! Text frames are treated as lines of input and output, and binary frames carry
+//! raw telnet bytes, so negotiation and GMCP work the same as they do over telnet.
+//!
+//! A client that sends a frame bigger than the larger of
+//! [`NestPlugin::read_buffer_size`](crate::plugin::NestPlugin::read_buffer_size) and
+//! the byte burst of the listener's [`InputLimit`](crate::limits::InputLimit) is
+//! disconnected.
+
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_util::{ready, Sink, Stream};
+use serde_json::Value;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_tungstenite::{tungstenite, WebSocketStream};
+
+use crate::{
+    telnet::{GMCP, IAC, SB, SE},
+    transport::Transport,
+};
+
+/// Settings for a WebSocket listener, passed to
+/// [`Server::listen_websocket`](crate::server::Server::listen_websocket).
+///
+/// ```rust,no_run
+/// use bevy::prelude::*;
+/// use bevy_nest::{prelude::*, websocket::WebSocketConfig};
+///
+/// fn setup_network(server: Res<Server>) {
+///     server.listen("0.0.0.0:4000");
+///     server.listen_websocket("0.0.0.0:4080", WebSocketConfig { gmcp_json: true });
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WebSocketConfig {
+    /// Send GMCP messages as JSON text frames instead of binary telnet frames, e.g.
+    /// `{"gmcp":"Char.Vitals","data":{"hp":10}}`. Text frames in the same shape
+    /// sent by the client are read as GMCP too.
+    pub gmcp_json: bool,
+}
+
+// Adapts a WebSocket connection to the byte stream the telnet pipeline expects.
+pub(crate) struct WebSocketAdapter {
+    stream: WebSocketStream<Box<dyn Transport>>,
+    config: WebSocketConfig,
+    // Bytes from the last frame that haven't been read yet.
+    read_buffer: Vec<u8>,
+    // Bytes written since the last flush, sent as a single frame.
+    write_buffer: Vec<u8>,
+}
+
+impl WebSocketAdapter {
+    pub(crate) async fn accept(
+        stream: Box<dyn Transport>,
+        config: WebSocketConfig,
+        max_message_size: usize,
+    ) -> io::Result<Self> {
+        let limits = tungstenite::protocol::WebSocketConfig {
+            max_message_size: Some(max_message_size),
+            max_frame_size: Some(max_message_size),
+            ..Default::default()
+        };
+
+        let stream = tokio_tungstenite::accept_async_with_config(stream, Some(limits))
+            .await
+            .map_err(io::Error::other)?;
+
+        Ok(Self {
+            stream,
+            config,
+            read_buffer: Vec::new(),
+            write_buffer: Vec::new(),
+        })
+    }
+
+    // Turn a frame from the client into telnet bytes.
+    fn decode(&self, message: tungstenite::Message) -> Option<Vec<u8>> {
+        match message {
+            tungstenite::Message::Text(text) => {
+                if self.config.gmcp_json {
+                    if let Some(gmcp) = gmcp_from_json(&text) {
+                        return Some(gmcp);
+                    }
+                }
+
+                Some((text + "\r\n").into_bytes())
+            }
+            tungstenite::Message::Binary(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    // Turn a single outgoing message into a frame.
+    fn encode(&self, bytes: Vec<u8>) -> tungstenite::Message {
+        if bytes.starts_with(&[IAC, SB, GMCP]) && bytes.ends_with(&[IAC, SE]) {
+            if self.config.gmcp_json {
+                if let Some(json) = gmcp_to_json(&bytes[3..bytes.len() - 2]) {
+                    return tungstenite::Message::Text(json);
+                }
+            }
+
+            return tungstenite::Message::Binary(bytes);
+        }
+
+        if bytes.contains(&IAC) {
+            return tungstenite::Message::Binary(bytes);
+        }
+
+        match String::from_utf8(bytes) {
+            Ok(text) => tungstenite::Message::Text(
+                text.strip_suffix("\r\n").map(String::from).unwrap_or(text),
+            ),
+            Err(err) => tungstenite::Message::Binary(err.into_bytes()),
+        }
+    }
+}
+
+impl AsyncRead for WebSocketAdapter {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        while this.read_buffer.is_empty() {
+            match ready!(Pin::new(&mut this.stream).poll_next(cx)) {
+                Some(Ok(tungstenite::Message::Close(_))) | None => return Poll::Ready(Ok(())),
+                Some(Ok(message)) => {
+                    if let Some(bytes) = this.decode(message) {
+                        this.read_buffer = bytes;
+                    }
+                }
+                Some(Err(err)) => return Poll::Ready(Err(io::Error::other(err))),
+            }
+        }
+
+        let length = buf.remaining().min(this.read_buffer.len());
+
+        buf.put_slice(&this.read_buffer[..length]);
+        this.read_buffer.drain(..length);
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for WebSocketAdapter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().write_buffer.extend_from_slice(buf);
+
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        if !this.write_buffer.is_empty() {
+            ready!(Pin::new(&mut this.stream).poll_ready(cx)).map_err(io::Error::other)?;
+
+            let bytes = std::mem::take(&mut this.write_buffer);
+            let frame = this.encode(bytes);
+
+            Pin::new(&mut this.stream)
+                .start_send(frame)
+                .map_err(io::Error::other)?;
+        }
+
+        Pin::new(&mut this.stream)
+            .poll_flush(cx)
+            .map_err(io::Error::other)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // Send anything still buffered before the close frame.
+        ready!(self.as_mut().poll_flush(cx))?;
+
+        Pin::new(&mut self.get_mut().stream)
+            .poll_close(cx)
+            .map_err(io::Error::other)
+    }
+}
+
+impl std::fmt::Debug for WebSocketAdapter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebSocketAdapter")
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+// `{"gmcp":"Package.Sub","data":...}` into `IAC SB GMCP Package.Sub data IAC SE`.
+fn gmcp_from_json(text: &str) -> Option<Vec<u8>> {
+    let value: Value = serde_json::from_str(text).ok()?;
+    let package = value.get("gmcp")?.as_str()?;
+
+    let mut seq = vec![IAC, SB, GMCP];
+
+    seq.extend(package.as_bytes());
+
+    match value.get("data") {
+        Some(Value::Null) | None => {}
+        Some(data) => {
+            seq.push(b' ');
+            seq.extend(data.to_string().as_bytes());
+        }
+    }
+
+    seq.extend([IAC, SE]);
+
+    Some(seq)
+}
+
+// `Package.Sub data` into `{"gmcp":"Package.Sub","data":...}`.
+fn gmcp_to_json(bytes: &[u8]) -> Option<String> {
+    let text = std::str::from_utf8(bytes).ok()?;
+
+    let (package, data) = match text.split_once(' ') {
+        Some((package, data)) => (
+            package,
+            // Data that isn't valid JSON is passed along as a string.
+            serde_json::from_str(data).unwrap_or_else(|_| Value::String(data.into())),
+        ),
+        None => (text, Value::Null),
+    };
+
+    Some(serde_json::json!({ "gmcp": package, "data": data }).to_string())
+}
+
+#[cfg(all(test, feature = "websocket"))]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        time::{Duration, Instant},
+    };
+
+    use bevy::prelude::*;
+    use futures_util::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::Message as Frame;
+
+    use super::WebSocketConfig;
+    use crate::prelude::*;
+
+    #[derive(Resource, Default)]
+    struct Seen {
+        addr: Option<SocketAddr>,
+        connected: bool,
+        disconnected: bool,
+        inbox: Vec<Message>,
+    }
+
+    fn watch(
+        mut seen: ResMut<Seen>,
+        mut events: EventReader<NetworkEvent>,
+        mut inbox: EventReader<Inbox>,
+        mut outbox: EventWriter<Outbox>,
+    ) {
+        for event in events.read() {
+            match event {
+                NetworkEvent::Listening { local_addr, .. } => seen.addr = Some(*local_addr),
+                NetworkEvent::Connected(..) => seen.connected = true,
+                NetworkEvent::Disconnected(..) => seen.disconnected = true,
+                _ => {}
+            }
+        }
+
+        for message in inbox.read() {
+            if message.content == Message::Text("hello".into()) {
+                outbox.send_gmcp(
+                    message.from,
+                    Payload {
+                        package: "Char".into(),
+                        subpackage: Some("Vitals".into()),
+                        data: Some(r#"{"hp":10}"#.into()),
+                    },
+                );
+            }
+
+            seen.inbox.push(message.content.clone());
+        }
+    }
+
+    fn update_until<T>(app: &mut App, mut done: impl FnMut(&Seen) -> Option<T>) -> T {
+        let started = Instant::now();
+
+        loop {
+            app.update();
+
+            if let Some(value) = done(app.world().resource::<Seen>()) {
+                return value;
+            }
+
+            assert!(started.elapsed() < Duration::from_secs(5), "Timed out");
+
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn listen(config: ListenerConfig) -> (App, SocketAddr) {
+        let mut app = App::new();
+
+        app.add_plugins(NestPlugin::default())
+            .init_resource::<Seen>()
+            .add_systems(Update, watch);
+
+        app.world().resource::<Server>().listen_websocket_with(
+            "127.0.0.1:0",
+            WebSocketConfig { gmcp_json: true },
+            config,
+        );
+
+        let addr = update_until(&mut app, |seen| seen.addr);
+
+        (app, addr)
+    }
+
+    #[test]
+    fn round_trip_text_and_gmcp() {
+        let (mut app, addr) = listen(ListenerConfig {
+            greeting: Some("Welcome!".into()),
+            ..default()
+        });
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let client = runtime.spawn(async move {
+            let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let (mut socket, _) = tokio_tungstenite::client_async("ws://localhost/", stream)
+                .await
+                .unwrap();
+
+            let greeting = socket.next().await.unwrap().unwrap();
+
+            socket.send(Frame::Text("hello".into())).await.unwrap();
+            socket
+                .send(Frame::Text(
+                    r#"{"gmcp":"Core.Hello","data":{"client":"test"}}"#.into(),
+                ))
+                .await
+                .unwrap();
+
+            let gmcp = socket.next().await.unwrap().unwrap();
+
+            (greeting, gmcp)
+        });
+
+        let inbox = update_until(&mut app, |seen| {
+            (seen.inbox.len() == 2).then(|| seen.inbox.clone())
+        });
+
+        assert!(app.world().resource::<Seen>().connected);
+        assert_eq!(inbox[0], Message::Text("hello".into()));
+        assert_eq!(
+            inbox[1],
+            Message::Command(
+                [
+                    &[IAC, SB, GMCP][..],
+                    br#"Core.Hello {"client":"test"}"#,
+                    &[IAC, SE]
+                ]
+                .concat()
+            )
+        );
+
+        let (greeting, gmcp) = runtime.block_on(client).unwrap();
+
+        assert_eq!(greeting, Frame::Text("Welcome!".into()));
+        assert_eq!(
+            gmcp,
+            Frame::Text(r#"{"data":{"hp":10},"gmcp":"Char.Vitals"}"#.into())
+        );
+    }
+
+    #[test]
+    fn oversized_frames_disconnect() {
+        let (mut app, addr) = listen(ListenerConfig::default());
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        runtime.spawn(async move {
+            let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let (mut socket, _) = tokio_tungstenite::client_async("ws://localhost/", stream)
+                .await
+                .unwrap();
+
+            let _ = socket.send(Frame::Text("x".repeat(64 * 1024))).await;
+            let _ = socket.next().await;
+        });
+
+        update_until(&mut app, |seen| seen.disconnected.then_some(()));
+
+        assert!(app.world().resource::<Seen>().inbox.is_empty());
+    }
+
+    #[test]
+    fn rejections_are_sent() {
+        let (mut app, addr) = listen(ListenerConfig {
+            max_clients: Some(0),
+            ..default()
+        });
+
+        app.insert_resource(ConnectionLimits {
+            full_message: Some("The game is full.".into()),
+            ..default()
+        });
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let client = runtime.spawn(async move {
+            let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let (mut socket, _) = tokio_tungstenite::client_async("ws://localhost/", stream)
+                .await
+                .unwrap();
+
+            (socket.next().await, socket.next().await)
+        });
+
+        let started = Instant::now();
+
+        while !client.is_finished() {
+            assert!(started.elapsed() < Duration::from_secs(5), "Timed out");
+
+            app.update();
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let (message, close) = runtime.block_on(client).unwrap();
+
+        assert_eq!(
+            message.unwrap().unwrap(),
+            Frame::Text("The game is full.".into())
+        );
+        assert!(matches!(close, Some(Ok(Frame::Close(_)))));
+        assert!(!app.world().resource::<Seen>().connected);
+    }
+}